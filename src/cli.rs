@@ -0,0 +1,758 @@
+//! The `notes` command line.
+//!
+//! Exit codes follow `sysexits.h`: 2 for wrong usage, 65 when a song cannot
+//! be read, 69 when no audio device can play it and 74 when a result cannot
+//! be written.
+
+use clap::{Arg, ArgMatches, Command};
+use cpal::traits::{DeviceTrait, HostTrait};
+use notes::model::length::NoteLength;
+use notes::model::looping::Loop;
+use notes::model::song::Song;
+use notes::model::tempo::Tempo;
+use notes::output::device::{self, ConfigRange, OutputRequest};
+use notes::output::wav::{WavFormat, WavSpec};
+use notes::output::{self, render_to_wav, CpalSink};
+use notes::parsing::scala::{self, KeyboardMapping, ScalaTuning};
+use notes::parsing::{midi, notation};
+use notes::synthesis::sample_next;
+use notes::synthesis::tuning;
+use std::fmt::Display;
+use std::path::Path;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// What went wrong, which decides the exit code.
+#[derive(Debug)]
+pub enum Failure {
+    /// The song could not be read.
+    Input(anyhow::Error),
+    /// No audio device could play it.
+    Device(anyhow::Error),
+    /// The result could not be written.
+    Output(anyhow::Error),
+}
+
+impl Failure {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Failure::Input(_) => 65,
+            Failure::Device(_) => 69,
+            Failure::Output(_) => 74,
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Failure::Input(error) | Failure::Device(error) | Failure::Output(error) => {
+                write!(f, "{:#}", error)
+            }
+        }
+    }
+}
+
+pub fn command() -> Command<'static> {
+    let file = Arg::new("file")
+        .required(true)
+        .help("Song in the text notation, or a Standard MIDI File (.mid)");
+    let sample_rate = Arg::new("sample-rate")
+        .long("sample-rate")
+        .short('r')
+        .takes_value(true)
+        .value_name("HZ")
+        .validator(|s| match s.parse::<u32>() {
+            Ok(hz) if hz > 0 => Ok(()),
+            _ => Err(format!("'{}' is not a positive sample rate", s)),
+        })
+        .help("Sample rate of the output");
+
+    Command::new("notes")
+        .about("Plays melodies written as text or MIDI")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new("play")
+                .about("Plays a song on an audio device")
+                .arg(file.clone())
+                .args(song_args())
+                .args(tuning_args())
+                .arg(loop_arg(true))
+                .arg(bars_arg())
+                .arg(sample_rate.clone())
+                .arg(host_arg())
+                .arg(
+                    Arg::new("device")
+                        .long("device")
+                        .short('d')
+                        .takes_value(true)
+                        .value_name("NAME|INDEX")
+                        .help("Output device by name or number, see `notes devices`"),
+                )
+                .arg(channels_arg())
+                .arg(
+                    Arg::new("sample-format")
+                        .long("sample-format")
+                        .takes_value(true)
+                        .possible_values(["f32", "i16", "u16"])
+                        .help("Sample format of the output stream"),
+                )
+                .arg(
+                    Arg::new("buffer-size")
+                        .long("buffer-size")
+                        .short('b')
+                        .takes_value(true)
+                        .value_name("FRAMES")
+                        .validator(|s| match s.parse::<u32>() {
+                            Ok(0) | Err(_) => Err(format!("'{}' is not a count of 1 or more", s)),
+                            Ok(_) => Ok(()),
+                        })
+                        .help("Frames the device asks for at a time"),
+                ),
+        )
+        .subcommand(
+            Command::new("render")
+                .about("Renders a song to a WAV file")
+                .arg(file.clone())
+                .arg(output_arg())
+                .args(song_args())
+                .args(tuning_args())
+                .arg(loop_arg(false))
+                .arg(bars_arg())
+                .arg(sample_rate.default_value("48000"))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .short('f')
+                        .takes_value(true)
+                        .possible_values(["pcm16", "pcm24", "f32"])
+                        .default_value("pcm16")
+                        .help("Sample format of the WAV file"),
+                )
+                .arg(channels_arg().default_value("2")),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Exports a song as a Standard MIDI File")
+                .arg(file.clone())
+                .arg(output_arg())
+                .args(song_args())
+                .arg(loop_arg(false)),
+        )
+        .subcommand(
+            Command::new("devices")
+                .about("Lists the audio hosts, their output devices and configurations")
+                .arg(host_arg()),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Describes the tracks of a song")
+                .arg(file)
+                .args(song_args())
+                .arg(loop_arg(false)),
+        )
+}
+
+fn host_arg() -> Arg<'static> {
+    Arg::new("host")
+        .long("host")
+        .takes_value(true)
+        .value_name("NAME")
+        .help("Audio host, such as ALSA or JACK, instead of the default one")
+}
+
+fn channels_arg() -> Arg<'static> {
+    Arg::new("channels")
+        .long("channels")
+        .short('c')
+        .takes_value(true)
+        .validator(|s| match s.parse::<u16>() {
+            Ok(0) => Err("needs at least one channel".to_string()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        })
+        .help("Number of output channels")
+}
+
+fn output_arg() -> Arg<'static> {
+    Arg::new("output")
+        .long("output")
+        .short('o')
+        .takes_value(true)
+        .value_name("PATH")
+        .required(true)
+        .help("File to write")
+}
+
+/// Far enough to move any MIDI key to any other.
+const MAX_TRANSPOSE: i32 = 127;
+
+/// Options that change the song itself.
+fn song_args() -> [Arg<'static>; 3] {
+    [
+        Arg::new("tempo")
+            .long("tempo")
+            .short('t')
+            .takes_value(true)
+            .value_name("BPM")
+            .validator(|s| match s.parse::<f64>() {
+                Ok(bpm) if bpm > 0.0 && bpm.is_finite() => Ok(()),
+                _ => Err(format!("'{}' is not a positive number of beats", s)),
+            })
+            .help("Quarter notes per minute, instead of the song's own tempo"),
+        Arg::new("transpose")
+            .long("transpose")
+            .takes_value(true)
+            .value_name("SEMITONES")
+            .allow_hyphen_values(true)
+            .validator(|s| match s.parse::<i32>() {
+                Ok(semitones) if (-MAX_TRANSPOSE..=MAX_TRANSPOSE).contains(&semitones) => Ok(()),
+                _ => Err(format!(
+                    "'{}' is not a number of semitones between -{1} and {1}",
+                    s, MAX_TRANSPOSE
+                )),
+            })
+            .help("Moves every pitch up, or down when negative"),
+        Arg::new("volume")
+            .long("volume")
+            .short('v')
+            .takes_value(true)
+            .value_name("GAIN")
+            .validator(|s| match s.parse::<f32>() {
+                Ok(gain) if gain >= 0.0 => Ok(()),
+                _ => Err(format!("'{}' is not a gain of 0 or more", s)),
+            })
+            .help("Linear gain on every track, 1 leaves them as they are"),
+    ]
+}
+
+/// Options for how pitches sound, which only matter to audio output.
+fn tuning_args() -> [Arg<'static>; 4] {
+    [
+        Arg::new("concert-pitch")
+            .long("concert-pitch")
+            .short('a')
+            .takes_value(true)
+            .value_name("HZ")
+            .validator(|s| match s.parse::<f32>() {
+                Ok(hz) if hz > 0.0 && hz.is_finite() => Ok(()),
+                _ => Err(format!("'{}' is not a positive frequency", s)),
+            })
+            .help("Frequency of A4, 440 by default; 415 for baroque pitch"),
+        Arg::new("tuning")
+            .long("tuning")
+            .takes_value(true)
+            .value_name("SYSTEM")
+            .validator(|s| tuning::parse_tuning_system(s).map(|_| ()))
+            .help(
+                "equal (the default), pythagorean, meantone, werckmeister3, \
+                 just:<tonic> such as just:D, or <n>edo such as 19edo",
+            ),
+        Arg::new("scala")
+            .long("scala")
+            .takes_value(true)
+            .value_name("FILE.scl")
+            .conflicts_with("tuning")
+            .help("Tunes to a Scala scale file instead"),
+        Arg::new("kbm")
+            .long("kbm")
+            .takes_value(true)
+            .value_name("FILE.kbm")
+            .requires("scala")
+            .help("Maps keys to the Scala scale by a keyboard mapping file"),
+    ]
+}
+
+/// The song of `loaded` in the tuning that `--tuning` or `--scala` and
+/// `--concert-pitch` ask for.
+fn tuned(matches: &ArgMatches, loaded: Loaded) -> Result<Loaded, Failure> {
+    let mut tuning = loaded.song.tuning.clone();
+    if let Some(system) = matches.value_of("tuning") {
+        tuning.system = tuning::parse_tuning_system(system).map_err(Failure::Input)?;
+    }
+    if let Some(path) = matches.value_of("scala") {
+        let scale = scala::load_scale(Path::new(path)).map_err(Failure::Input)?;
+        let mapping = match matches.value_of("kbm") {
+            Some(path) => scala::load_mapping(Path::new(path)).map_err(Failure::Input)?,
+            None => KeyboardMapping::default(),
+        };
+        let system = ScalaTuning::new(scale, mapping).map_err(Failure::Input)?;
+        tuning.system = Arc::new(system);
+    }
+    if let Some(concert_pitch) = optional(matches, "concert-pitch") {
+        tuning.concert_pitch = concert_pitch;
+    }
+    let song = loaded.song.with_tuning(tuning);
+    Ok(Loaded { song, ..loaded })
+}
+
+/// `--loop`, which only playback can repeat `forever`.
+fn loop_arg(forever: bool) -> Arg<'static> {
+    Arg::new("loop")
+        .long("loop")
+        .short('l')
+        .takes_value(true)
+        .value_name(if forever { "COUNT|forever" } else { "COUNT" })
+        .validator(move |s| match s.parse::<usize>() {
+            Ok(0) => Err(format!("'{}' is not a count of 1 or more", s)),
+            Ok(_) => Ok(()),
+            Err(_) if forever && s == "forever" => Ok(()),
+            Err(_) => Err(format!("'{}' is not a count of 1 or more", s)),
+        })
+        .help("How many times to play the song, or the bars")
+}
+
+fn bars_arg() -> Arg<'static> {
+    Arg::new("bars")
+        .long("bars")
+        .takes_value(true)
+        .value_name("FIRST-LAST")
+        .validator(|s| parse_bars(s).map(|_| ()))
+        .help("Loops only these bars, counted from 1")
+}
+
+/// `FIRST-LAST`, or a single bar.
+fn parse_bars(s: &str) -> Result<(usize, usize), String> {
+    let (first, last) = s.split_once('-').unwrap_or((s, s));
+    match (first.parse::<usize>(), last.parse::<usize>()) {
+        (Ok(first), Ok(last)) if 1 <= first && first <= last => Ok((first, last)),
+        _ => Err(format!("'{}' is not a range of bars such as 5-8", s)),
+    }
+}
+
+/// The value of `name` if it was given; arguments are validated while
+/// parsing, so this only fails on arguments that were not declared.
+fn optional<T>(matches: &ArgMatches, name: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    matches
+        .is_present(name)
+        .then(|| matches.value_of_t(name).unwrap_or_else(|e| e.exit()))
+}
+
+pub fn run() -> ExitCode {
+    let matches = command().get_matches();
+    let result = match matches.subcommand() {
+        Some(("play", matches)) => play(matches),
+        Some(("render", matches)) => render(matches),
+        Some(("export", matches)) => export(matches),
+        Some(("devices", matches)) => devices(matches),
+        Some(("info", matches)) => info(matches),
+        _ => unreachable!("clap requires a subcommand"),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("error: {}", failure);
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
+
+/// A song as loaded from a file, with the song options applied.
+struct Loaded {
+    song: Song,
+    tempo: Tempo,
+    /// Length of a bar, from the first time signature of a MIDI file and
+    /// 4/4 otherwise.
+    bar: NoteLength,
+}
+
+/// Loads the `file` argument with the song options applied.
+fn load(matches: &ArgMatches) -> Result<Loaded, Failure> {
+    let path = Path::new(matches.value_of("file").unwrap_or_default());
+    let is_midi = path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("mid") || extension.eq_ignore_ascii_case("midi")
+    });
+    let (mut song, tempo, bar) = if is_midi {
+        let import = midi::load_midi(path).map_err(Failure::Input)?;
+        for warning in &import.warnings {
+            eprintln!("{}: {}", path.display(), warning);
+        }
+        let bar = import
+            .time_signatures
+            .first()
+            .filter(|signature| signature.numerator > 0)
+            .map_or(NoteLength::whole(), |signature| {
+                NoteLength::new(signature.numerator.into(), signature.denominator.into())
+            });
+        (import.song, import.tempo, bar)
+    } else {
+        let song = notation::load_song(path).map_err(Failure::Input)?;
+        (song, Tempo::default(), NoteLength::whole())
+    };
+
+    if let Some(volume) = optional::<f32>(matches, "volume") {
+        for track in &mut song.tracks {
+            track.volume *= volume;
+        }
+    }
+    let song = song.transposed(optional(matches, "transpose").unwrap_or(0));
+    let tempo = optional(matches, "tempo").map_or(tempo, Tempo::new);
+    Ok(Loaded { song, tempo, bar })
+}
+
+/// The loop that `--loop` and `--bars` ask for, `None` to play the song
+/// once.
+fn looping(matches: &ArgMatches, loaded: &Loaded) -> Result<Option<Loop>, Failure> {
+    let times = match matches.value_of("loop") {
+        Some("forever") => None,
+        Some(count) => Some(count.parse().unwrap_or(1)),
+        None => Some(1),
+    };
+    let length = loaded.song.length();
+    let looping = match optional_bars(matches) {
+        Some((first, last)) => {
+            // Bars the song reaches into, so that no bar number is scaled
+            // past the end of the song.
+            let (bar, whole) = (loaded.bar, length);
+            let bars = (whole.numerator() as u128 * bar.denominator() as u128)
+                .div_ceil(whole.denominator() as u128 * bar.numerator() as u128);
+            if first as u128 > bars {
+                return Err(Failure::Input(anyhow::Error::msg(format!(
+                    "The song ends before bar {}",
+                    first
+                ))));
+            }
+            Loop::bars(first, last.min(bars as usize), bar)
+        }
+        None if times == Some(1) || length == NoteLength::ZERO => return Ok(None),
+        None => Loop::new(NoteLength::ZERO, length),
+    };
+    Ok(Some(Loop { times, ..looping }))
+}
+
+fn optional_bars(matches: &ArgMatches) -> Option<(usize, usize)> {
+    matches
+        .value_of("bars")
+        .and_then(|bars| parse_bars(bars).ok())
+}
+
+/// The song of `loaded` played `--loop` times over.
+fn repeated(matches: &ArgMatches, loaded: Loaded) -> Loaded {
+    let song = loaded.song.repeated(optional(matches, "loop").unwrap_or(1));
+    Loaded { song, ..loaded }
+}
+
+fn play(matches: &ArgMatches) -> Result<(), Failure> {
+    let loaded = tuned(matches, load(matches)?)?;
+    let looping = looping(matches, &loaded)?;
+    let output = OutputRequest {
+        host: matches.value_of("host").map(str::to_string),
+        device: optional(matches, "device"),
+        sample_rate: optional(matches, "sample-rate"),
+        channels: optional(matches, "channels"),
+        sample_format: matches
+            .value_of("sample-format")
+            .map(device::parse_sample_format)
+            .transpose()
+            .map_err(Failure::Device)?,
+        buffer_size: optional(matches, "buffer-size"),
+    };
+    let mut sink = CpalSink::open(&output).map_err(Failure::Device)?;
+    let name = sink.device.name().map_err(|e| Failure::Device(e.into()))?;
+    println!("Output device : {}", name);
+    for fallback in &sink.fallbacks {
+        eprintln!("{}", fallback);
+    }
+    println!(
+        "Output config : {:?}, {:?}",
+        sink.config, sink.sample_format
+    );
+    output::play_on(&mut sink, sample_next, loaded.song, loaded.tempo, looping)
+        .map_err(Failure::Device)
+}
+
+fn render(matches: &ArgMatches) -> Result<(), Failure> {
+    let loaded = tuned(matches, load(matches)?)?;
+    let looping = looping(matches, &loaded)?;
+    let spec = WavSpec {
+        sample_rate: matches.value_of_t_or_exit("sample-rate"),
+        channels: matches.value_of_t_or_exit("channels"),
+        format: matches.value_of_t_or_exit::<WavFormat>("format"),
+    };
+    let path = Path::new(matches.value_of("output").unwrap_or_default());
+    let frames =
+        render_to_wav(path, spec, loaded.song, loaded.tempo, looping).map_err(Failure::Output)?;
+    println!("Rendered {} frames to {}", frames, path.display());
+    Ok(())
+}
+
+fn export(matches: &ArgMatches) -> Result<(), Failure> {
+    let Loaded { song, tempo, .. } = repeated(matches, load(matches)?);
+    let path = Path::new(matches.value_of("output").unwrap_or_default());
+    midi::save_midi(path, &song, tempo).map_err(Failure::Output)?;
+    println!(
+        "Exported {} tracks to {}",
+        song.tracks.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn devices(matches: &ArgMatches) -> Result<(), Failure> {
+    let hosts = match matches.value_of("host") {
+        Some(name) => vec![device::host(Some(name)).map_err(Failure::Device)?],
+        None => cpal::available_hosts()
+            .into_iter()
+            .filter_map(|id| match cpal::host_from_id(id) {
+                Ok(host) => Some(host),
+                Err(e) => {
+                    eprintln!("{}: {}", id.name(), e);
+                    None
+                }
+            })
+            .collect(),
+    };
+    let default_host = cpal::default_host().id();
+
+    for host in hosts {
+        let marker = if host.id() == default_host {
+            " (default)"
+        } else {
+            ""
+        };
+        println!("{}{}", host.id().name(), marker);
+        let default = host
+            .default_output_device()
+            .and_then(|device| device.name().ok());
+        let devices = host
+            .output_devices()
+            .map_err(|e| Failure::Device(anyhow::Error::new(e)))?;
+        for (index, device) in devices.enumerate() {
+            // Keep the numbering, which --device goes by, past a device
+            // that cannot tell its name.
+            let name = match device.name() {
+                Ok(name) => name,
+                Err(e) => {
+                    println!("  {}: {}", index, e);
+                    continue;
+                }
+            };
+            let marker = if default.as_ref() == Some(&name) {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("  {}: {}{}", index, name, marker);
+            // A device that is busy may not say what it supports.
+            match device.supported_output_configs() {
+                Ok(configs) => {
+                    for config in configs {
+                        println!("       {}", ConfigRange::from(config));
+                    }
+                }
+                Err(e) => println!("       {}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn info(matches: &ArgMatches) -> Result<(), Failure> {
+    let Loaded { song, tempo, .. } = repeated(matches, load(matches)?);
+    let length = song.length();
+    println!(
+        "{} tracks, {} whole notes, {:.2} s at {} bpm",
+        song.tracks.len(),
+        length,
+        tempo.seconds(length),
+        tempo.bpm
+    );
+    for (index, track) in song.tracks.iter().enumerate() {
+        let notes = &track.melody.melody;
+        let pitches = notes.iter().flat_map(|note| note.pitches.iter());
+        let lowest = pitches.clone().min_by_key(|pitch| pitch.midi());
+        let highest = pitches.max_by_key(|pitch| pitch.midi());
+        let range = match (lowest, highest) {
+            (Some(lowest), Some(highest)) => format!("{} to {}", lowest, highest),
+            _ => "silent".to_string(),
+        };
+        let mut flags = String::new();
+        if track.mute {
+            flags += ", muted";
+        }
+        if track.solo && song.is_audible(index) {
+            flags += ", solo";
+        }
+        println!(
+            "  {}: {} notes, {}, {}, volume {}, pan {}{}",
+            track.name,
+            notes.iter().filter(|note| !note.is_rest()).count(),
+            range,
+            track.instrument.describe(),
+            track.volume,
+            track.pan,
+            flags
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notes::output::device::DeviceChoice;
+
+    #[test]
+    fn command_is_well_formed() {
+        command().debug_assert();
+    }
+
+    #[test]
+    fn parse_subcommands_and_options() {
+        let matches = command()
+            .try_get_matches_from([
+                "notes",
+                "render",
+                "song.txt",
+                "-o",
+                "out.wav",
+                "--transpose",
+                "-3",
+                "--loop",
+                "2",
+                "-t",
+                "90",
+            ])
+            .unwrap();
+        let (name, render) = matches.subcommand().unwrap();
+        assert_eq!(name, "render");
+        assert_eq!(optional::<i32>(render, "transpose"), Some(-3));
+        assert_eq!(optional::<usize>(render, "loop"), Some(2));
+        assert_eq!(optional::<f64>(render, "tempo"), Some(90.0));
+        assert_eq!(optional::<f32>(render, "volume"), None);
+        assert_eq!(render.value_of("format"), Some("pcm16"));
+
+        let matches = command()
+            .try_get_matches_from([
+                "notes", "play", "song.txt", "-d", "1", "-c", "6", "-b", "256",
+            ])
+            .unwrap();
+        let play = matches.subcommand_matches("play").unwrap();
+        assert_eq!(optional(play, "device"), Some(DeviceChoice::Index(1)));
+        assert_eq!(optional::<u16>(play, "channels"), Some(6));
+        assert_eq!(optional::<u32>(play, "buffer-size"), Some(256));
+
+        // Usage errors go to standard error, where clap exits with 2.
+        let misused = |args: &[&str]| {
+            command()
+                .try_get_matches_from(args)
+                .unwrap_err()
+                .use_stderr()
+        };
+        assert!(misused(&["notes", "render", "song.txt"]));
+        assert!(misused(&["notes", "play", "song.txt", "--loop", "0"]));
+        assert!(misused(&["notes", "play", "song.txt", "--tempo", "fast"]));
+        assert!(misused(&["notes", "play", "song.txt", "-b", "0"]));
+        assert!(misused(&[
+            "notes", "render", "a.txt", "-o", "a.wav", "-l", "forever"
+        ]));
+        assert!(misused(&["notes", "play", "song.txt", "--bars", "3-2"]));
+        assert!(misused(&[
+            "notes", "play", "song.txt", "--tuning", "17-tet"
+        ]));
+        assert!(misused(&["notes", "play", "song.txt", "-a", "-415"]));
+        assert!(misused(&["notes", "play", "song.txt", "-a", "inf"]));
+        assert!(misused(&["notes", "play", "song.txt", "--tempo", "inf"]));
+        assert!(misused(&[
+            "notes",
+            "play",
+            "song.txt",
+            "--transpose",
+            "2000"
+        ]));
+        assert!(misused(&[
+            "notes",
+            "play",
+            "song.txt",
+            "--transpose",
+            "-128"
+        ]));
+        assert!(misused(&["notes", "play", "song.txt", "-r", "0"]));
+        assert!(misused(&["notes", "play", "song.txt", "-a", "1e39"]));
+        assert!(misused(&[
+            "notes",
+            "play",
+            "song.txt",
+            "--kbm",
+            "white.kbm"
+        ]));
+        assert!(misused(&[
+            "notes", "play", "song.txt", "--scala", "a.scl", "--tuning", "equal"
+        ]));
+        assert!(misused(&[
+            "notes", "render", "song.txt", "-o", "a.wav", "-f", "mp3"
+        ]));
+    }
+
+    #[test]
+    fn loops_cover_the_requested_bars() {
+        let loaded = Loaded {
+            song: notation::parse_song("A4*4").unwrap(),
+            tempo: Tempo::default(),
+            bar: NoteLength::new(3, 4),
+        };
+        let looping = |args: &[&str]| {
+            let matches = command()
+                .try_get_matches_from(["notes", "play", "song.txt"].iter().chain(args))
+                .unwrap();
+            looping(matches.subcommand_matches("play").unwrap(), &loaded)
+        };
+
+        assert_eq!(looping(&[]).unwrap(), None);
+        assert_eq!(
+            looping(&["-l", "2"]).unwrap(),
+            Some(Loop::new(NoteLength::ZERO, NoteLength::new(4, 1)).with_times(2))
+        );
+        assert_eq!(
+            looping(&["--bars", "2-3", "-l", "forever"]).unwrap(),
+            Some(Loop::new(NoteLength::new(3, 4), NoteLength::new(9, 4)))
+        );
+        assert_eq!(
+            looping(&["--bars", "6"]).unwrap(),
+            Some(Loop::new(NoteLength::new(15, 4), NoteLength::new(18, 4)).with_times(1))
+        );
+        assert!(looping(&["--bars", "7"]).is_err());
+        assert!(looping(&["--bars", "5000000000000000000"]).is_err());
+        assert_eq!(
+            looping(&["--bars", "5-5000000000000000000"]).unwrap(),
+            Some(Loop::new(NoteLength::new(12, 4), NoteLength::new(18, 4)).with_times(1))
+        );
+        assert_eq!(parse_bars("5-8"), Ok((5, 8)));
+    }
+
+    #[test]
+    fn tuning_options_reach_the_song() {
+        let matches = command()
+            .try_get_matches_from(["notes", "play", "a.txt", "-a", "415", "--tuning", "just:D"])
+            .unwrap();
+        let loaded = Loaded {
+            song: notation::parse_song("A4 D4").unwrap(),
+            tempo: Tempo::default(),
+            bar: NoteLength::whole(),
+        };
+        let song = tuned(matches.subcommand_matches("play").unwrap(), loaded)
+            .unwrap()
+            .song;
+        assert_eq!(song.tuning.frequency(&"A4".parse().unwrap()), Some(415.0));
+        assert!(
+            (song.tuning.frequency(&"D4".parse().unwrap()).unwrap() - 415.0 / 1.5).abs() < 1e-3
+        );
+    }
+
+    #[test]
+    fn unreadable_songs_are_input_failures() {
+        let matches = command()
+            .try_get_matches_from(["notes", "info", "/nonexistent/song.txt"])
+            .unwrap();
+        let failure = info(matches.subcommand_matches("info").unwrap()).unwrap_err();
+        assert_eq!(failure.exit_code(), 65);
+        assert!(failure.to_string().contains("/nonexistent/song.txt"));
+    }
+}
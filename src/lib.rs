@@ -0,0 +1,38 @@
+//! Melodies and multi-track songs, played through a small synthesizer on
+//! an audio device, into memory, or into WAV and MIDI files.
+//!
+//! ```
+//! use cpal::SampleFormat;
+//! use notes::{play_on, sample_next, Melody, MemorySink, Note, Tempo, ToneLength};
+//!
+//! let melody = Melody::new()
+//!     .with_note(Note::new("C4".parse::<notes::Pitch>()?, ToneLength::Quarter))
+//!     .with_rest(ToneLength::Quarter)
+//!     .with_note(Note::new("G4".parse::<notes::Pitch>()?, ToneLength::Half));
+//! let mut sink = MemorySink::new(48000, 2, SampleFormat::F32);
+//! play_on(&mut sink, sample_next, melody.into(), Tempo::new(90.0), None)?;
+//! assert!(sink.frames() >= 96000);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+extern crate anyhow;
+extern crate cpal;
+
+pub mod model;
+pub mod output;
+pub mod parsing;
+pub mod synthesis;
+
+pub use model::length::{NoteLength, ToneLength};
+pub use model::looping::Loop;
+pub use model::pitch::Pitch;
+pub use model::song::{Song, Track};
+pub use model::tempo::Tempo;
+pub use model::{Melody, Note};
+pub use output::{play_on, render_to_wav, CpalSink, MemorySink, OutputSink, Samples};
+pub use parsing::notation::{parse_melody, parse_song};
+pub use synthesis::instrument::Instrument;
+pub use synthesis::pluck::PluckedString;
+pub use synthesis::synth::Synth;
+pub use synthesis::tuning::Tuning;
+pub use synthesis::{sample_next, SampleRequestOptions};
@@ -0,0 +1,306 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, Sub};
+
+/// An exact note length or position, as a fraction of a whole note.
+///
+/// The fraction is always kept in lowest terms, so the derived equality
+/// compares values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoteLength {
+    numerator: u64,
+    denominator: u64,
+}
+
+pub(crate) const OVERFLOW: &str = "a note length does not fit into 64 bits";
+
+pub(crate) fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+impl NoteLength {
+    pub const ZERO: NoteLength = NoteLength {
+        numerator: 0,
+        denominator: 1,
+    };
+
+    pub fn new(numerator: u64, denominator: u64) -> Self {
+        assert!(
+            denominator != 0,
+            "a note length needs a non-zero denominator"
+        );
+        let divisor = gcd(numerator, denominator).max(1);
+        NoteLength {
+            numerator: numerator / divisor,
+            denominator: denominator / divisor,
+        }
+    }
+
+    pub fn whole() -> Self {
+        NoteLength::new(1, 1)
+    }
+
+    /// A 1/`denominator` note, e.g. `NoteLength::fraction(4)` is a quarter.
+    pub fn fraction(denominator: u64) -> Self {
+        NoteLength::new(1, denominator)
+    }
+
+    pub fn numerator(&self) -> u64 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> u64 {
+        self.denominator
+    }
+
+    /// Each dot adds half of the previous addition: two dots make 7/4.
+    pub fn dotted(self, dots: u32) -> Self {
+        self.checked_dotted(dots).expect(OVERFLOW)
+    }
+
+    /// `dotted`, or `None` for more dots than the fraction can hold.
+    pub fn checked_dotted(self, dots: u32) -> Option<Self> {
+        let halves = 1u64.checked_shl(dots.checked_add(1)?)?;
+        self.checked_scaled(halves - 1, halves / 2)
+    }
+
+    /// `notes` notes in the time of `in_time_of`, e.g. `tuplet(3, 2)` for a
+    /// triplet.
+    pub fn tuplet(self, notes: u64, in_time_of: u64) -> Self {
+        self.scaled(in_time_of, notes)
+    }
+
+    pub fn scaled(self, numerator: u64, denominator: u64) -> Self {
+        self.checked_scaled(numerator, denominator).expect(OVERFLOW)
+    }
+
+    /// `scaled`, or `None` when the fraction does not fit into 64 bits.
+    pub fn checked_scaled(self, numerator: u64, denominator: u64) -> Option<Self> {
+        let left = gcd(self.numerator, denominator).max(1);
+        let right = gcd(numerator, self.denominator).max(1);
+        Some(NoteLength::new(
+            (self.numerator / left).checked_mul(numerator / right)?,
+            (self.denominator / right).checked_mul(denominator / left)?,
+        ))
+    }
+
+    /// The sum, or `None` when the fraction does not fit into 64 bits.
+    pub fn checked_add(self, other: NoteLength) -> Option<Self> {
+        let divisor = gcd(self.denominator, other.denominator);
+        let denominator = (self.denominator / divisor).checked_mul(other.denominator)?;
+        let numerator = self
+            .numerator
+            .checked_mul(denominator / self.denominator)?
+            .checked_add(
+                other
+                    .numerator
+                    .checked_mul(denominator / other.denominator)?,
+            )?;
+        Some(NoteLength::new(numerator, denominator))
+    }
+
+    /// The difference, or `None` when `other` is the longer one or the
+    /// fraction does not fit into 64 bits.
+    pub fn checked_sub(self, other: NoteLength) -> Option<Self> {
+        let divisor = gcd(self.denominator, other.denominator);
+        let denominator = (self.denominator / divisor).checked_mul(other.denominator)?;
+        let numerator = self
+            .numerator
+            .checked_mul(denominator / self.denominator)?
+            .checked_sub(
+                other
+                    .numerator
+                    .checked_mul(denominator / other.denominator)?,
+            )?;
+        Some(NoteLength::new(numerator, denominator))
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+}
+
+impl Default for NoteLength {
+    fn default() -> Self {
+        NoteLength::ZERO
+    }
+}
+
+impl Add for NoteLength {
+    type Output = NoteLength;
+
+    fn add(self, other: NoteLength) -> NoteLength {
+        self.checked_add(other).expect(OVERFLOW)
+    }
+}
+
+/// Panics when `other` is the longer one, as lengths cannot be negative.
+impl Sub for NoteLength {
+    type Output = NoteLength;
+
+    fn sub(self, other: NoteLength) -> NoteLength {
+        assert!(other <= self, "a note length cannot be negative");
+        self.checked_sub(other).expect(OVERFLOW)
+    }
+}
+
+impl Sum for NoteLength {
+    fn sum<I: Iterator<Item = NoteLength>>(iter: I) -> Self {
+        iter.fold(NoteLength::ZERO, Add::add)
+    }
+}
+
+impl Ord for NoteLength {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.numerator as u128 * other.denominator as u128)
+            .cmp(&(other.numerator as u128 * self.denominator as u128))
+    }
+}
+
+impl PartialOrd for NoteLength {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for NoteLength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+/// Convenient names for the common lengths, relative to a whole (`Full`)
+/// note.
+#[derive(Debug, Clone, Copy)]
+pub enum ToneLength {
+    Four,
+    FourDot,
+    Two,
+    TwoDot,
+    Full,
+    FullDot,
+    Half,
+    HalfDot,
+    Quarter,
+    QuarterDot,
+    Octet,
+}
+
+impl From<ToneLength> for NoteLength {
+    fn from(length: ToneLength) -> Self {
+        match length {
+            ToneLength::Four => NoteLength::new(4, 1),
+            ToneLength::FourDot => NoteLength::new(4, 1).dotted(1),
+            ToneLength::Two => NoteLength::new(2, 1),
+            ToneLength::TwoDot => NoteLength::new(2, 1).dotted(1),
+            ToneLength::Full => NoteLength::whole(),
+            ToneLength::FullDot => NoteLength::whole().dotted(1),
+            ToneLength::Half => NoteLength::fraction(2),
+            ToneLength::HalfDot => NoteLength::fraction(2).dotted(1),
+            ToneLength::Quarter => NoteLength::fraction(4),
+            ToneLength::QuarterDot => NoteLength::fraction(4).dotted(1),
+            ToneLength::Octet => NoteLength::fraction(8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tone_lengths_are_exact() {
+        assert_eq!(NoteLength::from(ToneLength::Octet), NoteLength::new(1, 8));
+        assert_eq!(
+            NoteLength::from(ToneLength::QuarterDot),
+            NoteLength::new(3, 8)
+        );
+        assert_eq!(NoteLength::from(ToneLength::FourDot), NoteLength::new(6, 1));
+        assert_eq!(NoteLength::from(ToneLength::HalfDot).as_f64(), 0.75);
+    }
+
+    #[test]
+    fn any_number_of_dots() {
+        let quarter = NoteLength::fraction(4);
+        assert_eq!(quarter.dotted(0), quarter);
+        assert_eq!(quarter.dotted(2), NoteLength::new(7, 16));
+        assert_eq!(quarter.dotted(3), NoteLength::new(15, 32));
+        assert_eq!(
+            NoteLength::whole().checked_dotted(62),
+            Some(NoteLength::new((1 << 63) - 1, 1 << 62))
+        );
+        assert_eq!(NoteLength::whole().checked_dotted(63), None);
+        assert_eq!(quarter.checked_dotted(62), None);
+        assert_eq!(quarter.checked_dotted(u32::MAX), None);
+    }
+
+    #[test]
+    fn checked_arithmetic_stops_at_overflow() {
+        let fine = NoteLength::fraction(4_294_967_311);
+        assert_eq!(
+            fine.checked_add(fine),
+            Some(NoteLength::new(2, 4_294_967_311))
+        );
+        assert_eq!(fine.checked_add(NoteLength::fraction(4_294_967_291)), None);
+        assert_eq!(fine.checked_scaled(1, 4_294_967_291), None);
+        assert_eq!(
+            fine.checked_scaled(4_294_967_311, 1),
+            Some(NoteLength::whole())
+        );
+        assert_eq!(fine.checked_sub(fine), Some(NoteLength::ZERO));
+        assert_eq!(fine.checked_sub(NoteLength::fraction(4_294_967_291)), None);
+        assert_eq!(NoteLength::ZERO.checked_sub(fine), None);
+    }
+
+    #[test]
+    fn tuplets_fill_their_span_exactly() {
+        let triplet = NoteLength::fraction(8).tuplet(3, 2);
+        assert_eq!(triplet, NoteLength::new(1, 12));
+        assert_eq!(
+            std::iter::repeat_n(triplet, 3).sum::<NoteLength>(),
+            NoteLength::fraction(4)
+        );
+
+        let quintuplet = NoteLength::fraction(16).tuplet(5, 4);
+        assert_eq!(
+            std::iter::repeat_n(quintuplet, 5).sum::<NoteLength>(),
+            NoteLength::fraction(4)
+        );
+    }
+
+    #[test]
+    fn long_sums_do_not_drift() {
+        let total: NoteLength =
+            std::iter::repeat_n(NoteLength::fraction(8).tuplet(3, 2), 12 * 10_000).sum();
+        assert_eq!(total, NoteLength::new(10_000, 1));
+    }
+
+    #[test]
+    fn ordering_compares_values() {
+        assert!(NoteLength::new(1, 3) < NoteLength::new(3, 8));
+        assert!(NoteLength::new(2, 4) == NoteLength::new(1, 2));
+        assert!(NoteLength::ZERO < NoteLength::fraction(1024));
+    }
+
+    #[test]
+    fn subtraction_is_exact() {
+        assert_eq!(
+            NoteLength::whole() - NoteLength::new(1, 3),
+            NoteLength::new(2, 3)
+        );
+        assert_eq!(
+            NoteLength::fraction(4) - NoteLength::fraction(4),
+            NoteLength::ZERO
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "a note length cannot be negative")]
+    fn subtraction_stays_positive() {
+        let _ = NoteLength::fraction(4) - NoteLength::fraction(2);
+    }
+}
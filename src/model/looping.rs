@@ -0,0 +1,128 @@
+use crate::model::length::NoteLength;
+use crate::model::tempo::Tempo;
+
+/// A stretch of a song played over and over, from `start` up to `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Loop {
+    pub start: NoteLength,
+    pub end: NoteLength,
+    /// How many passes to play, `None` to repeat until stopped.
+    pub times: Option<usize>,
+}
+
+impl Loop {
+    /// Repeats from `start` to `end` until stopped.
+    pub fn new(start: NoteLength, end: NoteLength) -> Self {
+        assert!(start < end, "a loop needs to end after it starts");
+        Loop {
+            start,
+            end,
+            times: None,
+        }
+    }
+
+    /// Bars `first` to `last`, counted from 1 and both included, of
+    /// `bar` whole notes each.
+    pub fn bars(first: usize, last: usize, bar: NoteLength) -> Self {
+        assert!(first >= 1, "bars are counted from 1");
+        Loop::new(bar.scaled(first as u64 - 1, 1), bar.scaled(last as u64, 1))
+    }
+
+    pub fn with_times(self, times: usize) -> Self {
+        Loop {
+            times: Some(times),
+            ..self
+        }
+    }
+
+    pub fn length(&self) -> NoteLength {
+        self.end - self.start
+    }
+
+    /// The pass and the position in the song reached `time` after the
+    /// loop started playing, or `None` once the last pass is over.
+    pub fn position(&self, time: NoteLength) -> Option<(usize, NoteLength)> {
+        let length = self.length();
+        let pass = (time.numerator() as u128 * length.denominator() as u128)
+            / (time.denominator() as u128 * length.numerator() as u128);
+        let pass = pass as usize;
+        if self.times.is_some_and(|times| pass >= times) {
+            return None;
+        }
+        let into = time - length.scaled(pass as u64, 1);
+        Some((pass, self.start + into))
+    }
+
+    /// Like `position`, in samples of the song.
+    ///
+    /// Every pass lasts exactly as many samples as the stretch does when
+    /// played straight through, so the seams never drift, and a note
+    /// boundary falls on the same sample in every pass.
+    pub fn sample(&self, sample: u64, tempo: Tempo, sample_rate: f32) -> Option<(usize, u64)> {
+        let start = tempo.sample_at(self.start, sample_rate);
+        let length = tempo.sample_at(self.end, sample_rate).saturating_sub(start);
+        if length == 0 {
+            return None;
+        }
+        let pass = (sample / length) as usize;
+        if self.times.is_some_and(|times| pass >= times) {
+            return None;
+        }
+        Some((pass, start + sample % length))
+    }
+
+    /// Samples until the last pass is over, `None` for a loop that never
+    /// ends.
+    pub fn duration_samples(&self, tempo: Tempo, sample_rate: f32) -> Option<u64> {
+        let length = tempo
+            .sample_at(self.end, sample_rate)
+            .saturating_sub(tempo.sample_at(self.start, sample_rate));
+        self.times.map(|times| length * times as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_wrap_to_the_start() {
+        let looping = Loop::bars(2, 3, NoteLength::new(3, 4)).with_times(2);
+        assert_eq!(looping.start, NoteLength::new(3, 4));
+        assert_eq!(looping.end, NoteLength::new(9, 4));
+
+        assert_eq!(
+            looping.position(NoteLength::ZERO),
+            Some((0, NoteLength::new(3, 4)))
+        );
+        assert_eq!(
+            looping.position(NoteLength::new(5, 4)),
+            Some((0, NoteLength::new(2, 1)))
+        );
+        assert_eq!(
+            looping.position(NoteLength::new(6, 4)),
+            Some((1, NoteLength::new(3, 4)))
+        );
+        assert_eq!(looping.position(NoteLength::new(3, 1)), None);
+
+        let forever = Loop::new(NoteLength::ZERO, NoteLength::whole());
+        assert_eq!(
+            forever.position(NoteLength::new(1001, 2)),
+            Some((500, NoteLength::fraction(2)))
+        );
+    }
+
+    #[test]
+    fn passes_last_a_whole_number_of_samples() {
+        // A triplet quarter at 100 bpm lasts 0.4 s, 17640 samples at 44.1 kHz.
+        let tempo = Tempo::new(100.0);
+        let triplet = NoteLength::fraction(4).tuplet(3, 2);
+        let looping = Loop::new(triplet, triplet.scaled(2, 1)).with_times(3);
+
+        assert_eq!(looping.sample(0, tempo, 44100.0), Some((0, 17640)));
+        assert_eq!(looping.sample(17639, tempo, 44100.0), Some((0, 35279)));
+        assert_eq!(looping.sample(17640, tempo, 44100.0), Some((1, 17640)));
+        assert_eq!(looping.sample(3 * 17640, tempo, 44100.0), None);
+        assert_eq!(looping.duration_samples(tempo, 44100.0), Some(3 * 17640));
+    }
+}
@@ -0,0 +1,303 @@
+//! Songs as written down: notes, melodies, tracks and their timing.
+
+pub mod length;
+pub mod looping;
+pub mod pitch;
+pub mod song;
+pub mod tempo;
+
+use length::NoteLength;
+use looping::Loop;
+use pitch::Pitch;
+use tempo::{Seconds, Tempo};
+
+pub type AbsoluteFrequency = f32;
+pub type RelativeFrequency = f32;
+
+pub static A_IN_HZ: AbsoluteFrequency = 440.0;
+
+/// One or more pitches sounding together, or a rest when there are none.
+///
+/// A `tie` holds the pitches that the next note repeats, so they keep
+/// sounding instead of being struck again. Notes tied to an identical chord
+/// sound as a single note.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub pitches: Vec<Pitch>,
+    pub length: NoteLength,
+    pub tie: bool,
+    /// How hard the note is struck, from 0 to 1.
+    pub velocity: f32,
+}
+
+impl Note {
+    /// Accepts a `Pitch` or, as before, a raw multiple of `A_IN_HZ`.
+    pub fn new(pitch: impl Into<Pitch>, length: impl Into<NoteLength>) -> Self {
+        Note::chord([pitch.into()], length)
+    }
+
+    pub fn chord(pitches: impl IntoIterator<Item = Pitch>, length: impl Into<NoteLength>) -> Self {
+        Note {
+            pitches: pitches.into_iter().collect(),
+            length: length.into(),
+            tie: false,
+            velocity: 1.0,
+        }
+    }
+
+    pub fn rest(length: impl Into<NoteLength>) -> Self {
+        Note::chord([], length)
+    }
+
+    pub fn tied(self) -> Self {
+        Note { tie: true, ..self }
+    }
+
+    pub fn with_velocity(self, velocity: f32) -> Self {
+        Note { velocity, ..self }
+    }
+
+    pub fn is_rest(&self) -> bool {
+        self.pitches.is_empty()
+    }
+
+    fn continues_into(&self, next: &Note) -> bool {
+        self.tie && !self.is_rest() && self.pitches == next.pitches
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Melody {
+    pub melody: Vec<Note>,
+}
+
+impl Melody {
+    /// A melody without notes, to add them to with the `with_*` methods.
+    pub fn new() -> Self {
+        Melody::default()
+    }
+
+    pub fn with_note(mut self, note: Note) -> Self {
+        self.melody.push(note);
+        self
+    }
+
+    pub fn with_notes(mut self, notes: impl IntoIterator<Item = Note>) -> Self {
+        self.melody.extend(notes);
+        self
+    }
+
+    pub fn with_rest(self, length: impl Into<NoteLength>) -> Self {
+        self.with_note(Note::rest(length))
+    }
+
+    pub fn length(&self) -> NoteLength {
+        self.melody.iter().map(|note| note.length).sum()
+    }
+
+    /// The first pitch sounding at `time`, or `None` during a rest.
+    pub fn pitch_at(&self, time: Seconds, tempo: Tempo) -> Option<RelativeFrequency> {
+        self.pitches_at(time, tempo)
+            .first()
+            .map(|pitch| pitch.relative_to_a())
+    }
+
+    /// All pitches sounding at `time`, none during a rest.
+    pub fn pitches_at(&self, time: Seconds, tempo: Tempo) -> &[Pitch] {
+        match self.sounding_note(|end| tempo.seconds(end) <= time) {
+            Some(index) => &self.melody[index].pitches,
+            None => &[],
+        }
+    }
+
+    /// Index of the note sounding at `time_in_beat`, or `None` during a rest.
+    ///
+    /// A note reached through ties resolves to the first note of the tie
+    /// chain, so a tied note is not struck again.
+    pub fn beat_to_note(&self, time_in_beat: NoteLength) -> Option<usize> {
+        self.sounding_note(|end| end <= time_in_beat)
+    }
+
+    /// Like `beat_to_note`, for `time_in_beat` since `looping` started:
+    /// past its end the lookup wraps back to its start.
+    pub fn looped_beat_to_note(&self, time_in_beat: NoteLength, looping: &Loop) -> Option<usize> {
+        let (_, position) = looping.position(time_in_beat)?;
+        self.beat_to_note(position)
+    }
+
+    /// Finds the note after all those whose end has `passed`.
+    fn sounding_note<F>(&self, passed: F) -> Option<usize>
+    where
+        F: Fn(NoteLength) -> bool,
+    {
+        let mut end = NoteLength::ZERO;
+        let index = self.melody.iter().position(|note| {
+            end = end + note.length;
+            !passed(end)
+        })?;
+        self.strike(index)
+    }
+
+    /// The note that sounds during note `index`: the first note of its tie
+    /// chain, or `None` for a rest.
+    pub(crate) fn strike(&self, mut index: usize) -> Option<usize> {
+        if self.melody[index].is_rest() {
+            return None;
+        }
+        while index > 0 && self.melody[index - 1].continues_into(&self.melody[index]) {
+            index -= 1;
+        }
+        Some(index)
+    }
+}
+
+impl FromIterator<Note> for Melody {
+    fn from_iter<I: IntoIterator<Item = Note>>(notes: I) -> Self {
+        Melody::new().with_notes(notes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use length::ToneLength;
+
+    fn note(pitch: &str, length: impl Into<NoteLength>) -> Note {
+        Note::new(pitch.parse::<Pitch>().unwrap(), length)
+    }
+
+    fn one_full_note_per_minute() -> Tempo {
+        Tempo::new(1.0).with_beat(NoteLength::whole())
+    }
+
+    #[test]
+    fn get_tone_first_tone_of_melody() {
+        let my_melody = Melody {
+            melody: vec![note("A4", ToneLength::Full)],
+        };
+        assert_eq!(
+            my_melody.pitch_at(0.0, one_full_note_per_minute()),
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn get_tone_second_tone_of_melody() {
+        let my_melody = Melody {
+            melody: vec![note("A4", ToneLength::Full), note("A5", ToneLength::Full)],
+        };
+        assert_eq!(
+            my_melody.pitch_at(61.0, one_full_note_per_minute()),
+            Some(2.0)
+        );
+    }
+
+    #[test]
+    fn get_tone_first_tone_of_daa_da_melody() {
+        let my_melody = Melody {
+            melody: vec![note("A4", ToneLength::Two), note("A5", ToneLength::Full)],
+        };
+        assert_eq!(
+            my_melody.pitch_at(61.0, one_full_note_per_minute()),
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn raw_multipliers_still_construct_notes() {
+        let my_melody = Melody {
+            melody: vec![Note::new(2.0, ToneLength::Full)],
+        };
+        assert_eq!(my_melody.melody[0].pitches[0].to_string(), "A5");
+        assert_eq!(
+            my_melody.pitch_at(0.0, one_full_note_per_minute()),
+            Some(2.0)
+        );
+    }
+
+    #[test]
+    fn ties_merge_into_the_first_note() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", ToneLength::Full).tied(),
+                note("A4", ToneLength::Full).tied(),
+                note("A4", ToneLength::Full),
+                note("A4", ToneLength::Full),
+            ],
+        };
+        assert_eq!(my_melody.beat_to_note(NoteLength::new(1, 2)), Some(0));
+        assert_eq!(my_melody.beat_to_note(NoteLength::new(3, 2)), Some(0));
+        assert_eq!(my_melody.beat_to_note(NoteLength::new(5, 2)), Some(0));
+        assert_eq!(my_melody.beat_to_note(NoteLength::new(7, 2)), Some(3));
+    }
+
+    #[test]
+    fn ties_between_different_pitches_retrigger() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", ToneLength::Full).tied(),
+                note("A5", ToneLength::Full),
+            ],
+        };
+        assert_eq!(my_melody.beat_to_note(NoteLength::new(3, 2)), Some(1));
+    }
+
+    #[test]
+    fn positions_are_exact_over_long_melodies() {
+        let triplet = NoteLength::fraction(8).tuplet(3, 2);
+        let mut notes = vec![note("A4", triplet); 12 * 1000];
+        notes.push(note("A5", ToneLength::Full));
+        let my_melody = Melody { melody: notes };
+
+        assert_eq!(
+            my_melody.beat_to_note(NoteLength::new(1000, 1)),
+            Some(12 * 1000)
+        );
+        assert_eq!(
+            my_melody.beat_to_note(NoteLength::new(1000 * 24 - 1, 24)),
+            Some(12 * 1000 - 1)
+        );
+    }
+
+    #[test]
+    fn beat_lookup_wraps_around_the_loop() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", ToneLength::Quarter),
+                note("C5", ToneLength::Quarter),
+                note("E5", ToneLength::Half),
+            ],
+        };
+        let looping = Loop::new(NoteLength::fraction(4), NoteLength::whole());
+        let at = |time| my_melody.looped_beat_to_note(time, &looping);
+        assert_eq!(at(NoteLength::ZERO), Some(1));
+        assert_eq!(at(NoteLength::fraction(2)), Some(2));
+        assert_eq!(at(NoteLength::new(3, 4)), Some(1));
+        assert_eq!(at(NoteLength::new(31, 4)), Some(2));
+        assert_eq!(
+            at(NoteLength::new(3, 4)),
+            my_melody.beat_to_note(NoteLength::fraction(4))
+        );
+
+        assert_eq!(
+            my_melody.looped_beat_to_note(NoteLength::new(3, 4), &looping.with_times(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn builders_append_notes_in_order() {
+        let built = Melody::new()
+            .with_note(note("A4", ToneLength::Quarter).tied())
+            .with_notes([
+                note("A4", ToneLength::Quarter),
+                note("C5", ToneLength::Half),
+            ])
+            .with_rest(ToneLength::Full);
+        assert_eq!(built.melody.len(), 4);
+        assert_eq!(built.length(), NoteLength::new(2, 1));
+        assert_eq!(built.beat_to_note(NoteLength::new(3, 8)), Some(0));
+        assert_eq!(built.beat_to_note(NoteLength::new(3, 2)), None);
+        assert_eq!(built, built.melody.iter().cloned().collect());
+    }
+}
@@ -0,0 +1,297 @@
+use crate::model::{AbsoluteFrequency, RelativeFrequency, A_IN_HZ};
+use std::fmt;
+
+const A4_MIDI: i32 = 69;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Letter {
+    C,
+    D,
+    E,
+    F,
+    G,
+    A,
+    B,
+}
+
+impl Letter {
+    fn semitones_above_c(self) -> i32 {
+        match self {
+            Letter::C => 0,
+            Letter::D => 2,
+            Letter::E => 4,
+            Letter::F => 5,
+            Letter::G => 7,
+            Letter::A => 9,
+            Letter::B => 11,
+        }
+    }
+
+    fn from_char(c: char) -> Option<Letter> {
+        match c.to_ascii_uppercase() {
+            'C' => Some(Letter::C),
+            'D' => Some(Letter::D),
+            'E' => Some(Letter::E),
+            'F' => Some(Letter::F),
+            'G' => Some(Letter::G),
+            'A' => Some(Letter::A),
+            'B' => Some(Letter::B),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Letter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A spelled pitch in scientific pitch notation, e.g. `F#3` or `Bb5`.
+///
+/// `accidental` counts sharps (positive) or flats (negative), `cents`
+/// detunes the pitch for values that fall between the semitones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pitch {
+    pub letter: Letter,
+    pub accidental: i8,
+    pub octave: i8,
+    pub cents: f32,
+}
+
+impl Pitch {
+    pub fn new(letter: Letter, accidental: i8, octave: i8) -> Self {
+        Pitch {
+            letter,
+            accidental,
+            octave,
+            cents: 0.0,
+        }
+    }
+
+    /// Spells a MIDI note number, preferring sharps (61 is `C#4`).
+    pub fn from_midi(note: i32) -> Self {
+        const SPELLING: [(Letter, i8); 12] = [
+            (Letter::C, 0),
+            (Letter::C, 1),
+            (Letter::D, 0),
+            (Letter::D, 1),
+            (Letter::E, 0),
+            (Letter::F, 0),
+            (Letter::F, 1),
+            (Letter::G, 0),
+            (Letter::G, 1),
+            (Letter::A, 0),
+            (Letter::A, 1),
+            (Letter::B, 0),
+        ];
+        let (letter, accidental) = SPELLING[note.rem_euclid(12) as usize];
+        Pitch::new(letter, accidental, (note.div_euclid(12) - 1) as i8)
+    }
+
+    /// Nearest pitch to `ratio` times `A_IN_HZ`, with the rest kept in `cents`.
+    pub fn from_relative_frequency(ratio: RelativeFrequency) -> Self {
+        let semitones = 12.0 * ratio.log2();
+        let nearest = semitones.round();
+        Pitch {
+            cents: (semitones - nearest) * 100.0,
+            ..Pitch::from_midi(A4_MIDI + nearest as i32)
+        }
+    }
+
+    /// Moved by `semitones`, spelled afresh as by `from_midi`, but no
+    /// further than the lowest or highest octave there is.
+    pub fn transposed(self, semitones: i32) -> Self {
+        if semitones == 0 {
+            return self;
+        }
+        let lowest = Pitch::new(Letter::C, 0, i8::MIN).midi();
+        let highest = Pitch::new(Letter::B, 0, i8::MAX).midi();
+        Pitch {
+            cents: self.cents,
+            ..Pitch::from_midi(self.midi().saturating_add(semitones).clamp(lowest, highest))
+        }
+    }
+
+    pub fn detuned(self, cents: f32) -> Self {
+        Pitch {
+            cents: self.cents + cents,
+            ..self
+        }
+    }
+
+    /// MIDI note number, ignoring `cents`.
+    pub fn midi(&self) -> i32 {
+        (self.octave as i32 + 1) * 12 + self.letter.semitones_above_c() + self.accidental as i32
+    }
+
+    pub fn relative_to_a(&self) -> RelativeFrequency {
+        (((self.midi() - A4_MIDI) as f32 + self.cents / 100.0) / 12.0).exp2()
+    }
+
+    pub fn frequency(&self) -> AbsoluteFrequency {
+        A_IN_HZ * self.relative_to_a()
+    }
+}
+
+impl From<RelativeFrequency> for Pitch {
+    fn from(ratio: RelativeFrequency) -> Self {
+        Pitch::from_relative_frequency(ratio)
+    }
+}
+
+impl fmt::Display for Pitch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let accidental = if self.accidental >= 0 { "#" } else { "b" };
+        write!(
+            f,
+            "{}{}{}",
+            self.letter,
+            accidental.repeat(self.accidental.unsigned_abs() as usize),
+            self.octave
+        )?;
+        if self.cents != 0.0 {
+            write!(f, "{:+}c", self.cents)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Pitch {
+    type Err = anyhow::Error;
+
+    /// Accepts `C4`, `F#3`, `Bb5`, `Cx4`/`C##4`, `Ebb2`, `C-1`, an optional
+    /// cents suffix such as `A4+12c`, or a bare MIDI note number like `60`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |reason: &str| anyhow::Error::msg(format!("Invalid pitch '{}': {}", s, reason));
+
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+            let note: i32 = s.parse()?;
+            if note > 127 {
+                return Err(err("MIDI note numbers range from 0 to 127"));
+            }
+            return Ok(Pitch::from_midi(note));
+        }
+
+        let mut chars = s.chars().peekable();
+        let letter = chars
+            .next()
+            .and_then(Letter::from_char)
+            .ok_or_else(|| err("expected a note letter A-G"))?;
+
+        let mut accidental: i8 = 0;
+        while let Some(&c) = chars.peek() {
+            let step: i8 = match c {
+                '#' | '♯' => 1,
+                'x' | '𝄪' => 2,
+                'b' | '♭' => -1,
+                '𝄫' => -2,
+                _ => break,
+            };
+            if accidental != 0 && accidental.signum() != step.signum() {
+                return Err(err("cannot mix sharps and flats"));
+            }
+            accidental += step;
+            chars.next();
+        }
+        if accidental.abs() > 2 {
+            return Err(err("at most a double sharp or double flat is allowed"));
+        }
+
+        let rest: String = chars.collect();
+        let cents_sign = rest
+            .get(1..)
+            .and_then(|tail| tail.find(['+', '-']))
+            .map(|i| i + 1);
+        let (octave, cents) = match cents_sign {
+            Some(split) => {
+                let cents = rest[split..]
+                    .strip_suffix('c')
+                    .ok_or_else(|| err("cents must end in 'c'"))?;
+                (&rest[..split], cents.parse().map_err(|_| err("bad cents"))?)
+            }
+            None => (rest.as_str(), 0.0),
+        };
+        let octave = octave
+            .parse()
+            .map_err(|_| err("expected an octave number"))?;
+
+        Ok(Pitch {
+            letter,
+            accidental,
+            octave,
+            cents,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pitch(s: &str) -> Pitch {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_scientific_pitch_notation() {
+        assert_eq!(pitch("C4"), Pitch::new(Letter::C, 0, 4));
+        assert_eq!(pitch("F#3"), Pitch::new(Letter::F, 1, 3));
+        assert_eq!(pitch("Bb5"), Pitch::new(Letter::B, -1, 5));
+        assert_eq!(pitch("Cx4"), Pitch::new(Letter::C, 2, 4));
+        assert_eq!(pitch("C##4"), Pitch::new(Letter::C, 2, 4));
+        assert_eq!(pitch("Ebb2"), Pitch::new(Letter::E, -2, 2));
+        assert_eq!(pitch("g-1"), Pitch::new(Letter::G, 0, -1));
+        assert_eq!(pitch("A4-30c"), Pitch::new(Letter::A, 0, 4).detuned(-30.0));
+    }
+
+    #[test]
+    fn parse_midi_note_numbers() {
+        assert_eq!(pitch("60"), pitch("C4"));
+        assert_eq!(pitch("69"), pitch("A4"));
+        assert_eq!(pitch("0"), pitch("C-1"));
+        assert!("128".parse::<Pitch>().is_err());
+    }
+
+    #[test]
+    fn reject_malformed_pitches() {
+        for s in ["", "H4", "C", "C#b4", "C###4", "C4x", "A4+12"] {
+            assert!(s.parse::<Pitch>().is_err(), "{} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn midi_numbers_respect_accidentals() {
+        assert_eq!(pitch("C4").midi(), 60);
+        assert_eq!(pitch("B#3").midi(), 60);
+        assert_eq!(pitch("Cb4").midi(), 59);
+        assert_eq!(Pitch::from_midi(61), pitch("C#4"));
+    }
+
+    #[test]
+    fn transpose_by_semitones() {
+        assert_eq!(pitch("Bb4").transposed(0), pitch("Bb4"));
+        assert_eq!(pitch("Bb4").transposed(2), pitch("C5"));
+        assert_eq!(pitch("C4").transposed(-13), pitch("B2"));
+        assert_eq!(pitch("A4+20c").transposed(1), pitch("A#4+20c"));
+        assert_eq!(pitch("A4").transposed(2000), pitch("B127"));
+        assert_eq!(pitch("A4").transposed(i32::MAX), pitch("B127"));
+        assert_eq!(pitch("A4").transposed(i32::MIN), pitch("C-128"));
+    }
+
+    #[test]
+    fn frequencies_relative_to_a() {
+        assert_eq!(pitch("A4").relative_to_a(), 1.0);
+        assert_eq!(pitch("A5").relative_to_a(), 2.0);
+        assert_eq!(pitch("A3").frequency(), 220.0);
+        assert!((pitch("C4").frequency() - 261.6256).abs() < 1e-3);
+        assert!((Pitch::from(1.2).relative_to_a() - 1.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in ["C4", "F#3", "Bb5", "C##4", "Ebb2", "C-1", "A4-30c"] {
+            assert_eq!(pitch(s).to_string(), s);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::model::length::{NoteLength, OVERFLOW};
+use crate::model::{Melody, Note};
+use crate::synthesis::instrument::Instrument;
+use crate::synthesis::synth::Synth;
+use crate::synthesis::tuning::Tuning;
+
+/// One part of a `Song`, such as the melody, the bass or the drums, played
+/// by its own instrument.
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub name: String,
+    pub melody: Melody,
+    /// Plays the notes, the built-in `Synth` unless set otherwise.
+    pub instrument: Box<dyn Instrument>,
+    /// Linear gain, 1 leaves the track as it is.
+    pub volume: f32,
+    /// From -1 (left) over 0 (centre) to 1 (right).
+    pub pan: f32,
+    /// How far the pitches of a chord spread around `pan`: 0 stacks them
+    /// all at `pan`, 1 moves the lowest and the highest a whole side away.
+    pub width: f32,
+    /// Output channels, counted from 0, that the left and right side go to
+    /// on devices with more than two channels.
+    pub channels: [usize; 2],
+    pub mute: bool,
+    pub solo: bool,
+}
+
+impl Track {
+    pub fn new(name: impl Into<String>, melody: Melody) -> Self {
+        Track {
+            name: name.into(),
+            melody,
+            instrument: Box::new(Synth::default()),
+            volume: 1.0,
+            pan: 0.0,
+            width: 0.0,
+            channels: [0, 1],
+            mute: false,
+            solo: false,
+        }
+    }
+
+    pub fn with_instrument(self, instrument: impl Instrument + 'static) -> Self {
+        Track {
+            instrument: Box::new(instrument),
+            ..self
+        }
+    }
+
+    pub fn with_volume(self, volume: f32) -> Self {
+        Track { volume, ..self }
+    }
+
+    pub fn with_pan(self, pan: f32) -> Self {
+        Track { pan, ..self }
+    }
+
+    pub fn with_width(self, width: f32) -> Self {
+        Track { width, ..self }
+    }
+
+    pub fn with_channels(self, left: usize, right: usize) -> Self {
+        Track {
+            channels: [left, right],
+            ..self
+        }
+    }
+}
+
+/// Tracks that play at the same time and are mixed into one output.
+#[derive(Debug, Clone, Default)]
+pub struct Song {
+    pub tracks: Vec<Track>,
+    /// How every track turns its pitches into frequencies.
+    pub tuning: Tuning,
+}
+
+impl Song {
+    pub fn new(tracks: Vec<Track>) -> Self {
+        Song {
+            tracks,
+            tuning: Tuning::default(),
+        }
+    }
+
+    pub fn with_tuning(self, tuning: Tuning) -> Self {
+        Song { tuning, ..self }
+    }
+
+    /// Length of the longest track.
+    pub fn length(&self) -> NoteLength {
+        self.tracks
+            .iter()
+            .map(|track| track.melody.length())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Every pitch of every track moved by `semitones`.
+    pub fn transposed(mut self, semitones: i32) -> Self {
+        for track in &mut self.tracks {
+            for note in &mut track.melody.melody {
+                for pitch in &mut note.pitches {
+                    *pitch = pitch.transposed(semitones);
+                }
+            }
+        }
+        self
+    }
+
+    /// Every track played `times` times over, shorter tracks waiting for
+    /// the longest one before they start again.
+    pub fn repeated(mut self, times: usize) -> Self {
+        let length = self.length();
+        for track in &mut self.tracks {
+            let missing = length.checked_sub(track.melody.length()).expect(OVERFLOW);
+            if missing > NoteLength::ZERO {
+                track.melody.melody.push(Note::rest(missing));
+            }
+            let notes = &track.melody.melody;
+            track.melody.melody = notes
+                .iter()
+                .cycle()
+                .take(notes.len() * times)
+                .cloned()
+                .collect();
+        }
+        self
+    }
+
+    /// Whether track `index` is heard in the mix: once any track is soloed
+    /// only soloed tracks play, and a muted track never does.
+    pub fn is_audible(&self, index: usize) -> bool {
+        let track = &self.tracks[index];
+        let soloing = self.tracks.iter().any(|track| track.solo);
+        !track.mute && (track.solo || !soloing)
+    }
+}
+
+impl From<Melody> for Song {
+    fn from(melody: Melody) -> Self {
+        Song::new(vec![Track::new("melody", melody)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::length::ToneLength;
+
+    fn track(name: &str) -> Track {
+        Track::new(
+            name,
+            Melody {
+                melody: vec![Note::new(1.0, ToneLength::Full)],
+            },
+        )
+    }
+
+    #[test]
+    fn solo_silences_the_other_tracks() {
+        let mut song = Song::new(vec![track("melody"), track("bass"), track("drums")]);
+        assert!((0..3).all(|index| song.is_audible(index)));
+
+        song.tracks[2].mute = true;
+        assert!(song.is_audible(1) && !song.is_audible(2));
+
+        song.tracks[1].solo = true;
+        assert!(!song.is_audible(0) && song.is_audible(1));
+
+        song.tracks[1].mute = true;
+        assert!(!song.is_audible(1));
+    }
+
+    #[test]
+    fn transpose_and_repeat() {
+        let song = Song::new(vec![track("melody")]).transposed(-12).repeated(3);
+        let melody = &song.tracks[0].melody;
+        assert_eq!(melody.melody.len(), 3);
+        assert_eq!(melody.melody[2].pitches[0].to_string(), "A3");
+        assert_eq!(song.length(), NoteLength::new(3, 1));
+
+        let mut bass = track("bass");
+        bass.melody.melody[0].length = NoteLength::fraction(2);
+        let song = Song::new(vec![track("melody"), bass]).repeated(2);
+        let bass = &song.tracks[1].melody;
+        assert_eq!(bass.melody.len(), 4);
+        assert!(bass.melody[1].is_rest());
+        assert_eq!(bass.length(), NoteLength::new(2, 1));
+    }
+
+    #[test]
+    fn song_lasts_as_long_as_its_longest_track() {
+        let mut bass = track("bass");
+        bass.melody.melody.push(Note::rest(ToneLength::Half));
+        let song = Song::new(vec![track("melody"), bass]);
+        assert_eq!(song.length(), NoteLength::new(3, 2));
+    }
+}
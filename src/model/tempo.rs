@@ -0,0 +1,76 @@
+use crate::model::length::NoteLength;
+
+pub type Seconds = f64;
+
+/// Beats per minute, where a beat is a `beat` long note.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tempo {
+    pub bpm: f64,
+    pub beat: NoteLength,
+}
+
+impl Tempo {
+    /// `bpm` quarter notes per minute.
+    pub fn new(bpm: f64) -> Self {
+        Tempo {
+            bpm,
+            beat: NoteLength::fraction(4),
+        }
+    }
+
+    pub fn with_beat(self, beat: NoteLength) -> Self {
+        Tempo { beat, ..self }
+    }
+
+    /// Time from the start of the melody to `position`.
+    pub fn seconds(&self, position: NoteLength) -> Seconds {
+        let beats = (position.numerator() as u128 * self.beat.denominator() as u128) as f64
+            / (position.denominator() as u128 * self.beat.numerator() as u128) as f64;
+        beats * 60.0 / self.bpm
+    }
+
+    /// The sample nearest to `position`.
+    ///
+    /// Every boundary is computed from its exact position, so rounding
+    /// errors never add up over the length of a melody.
+    pub fn sample_at(&self, position: NoteLength, sample_rate: f32) -> u64 {
+        (self.seconds(position) * sample_rate as f64).round() as u64
+    }
+}
+
+impl Default for Tempo {
+    fn default() -> Self {
+        Tempo::new(120.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarter_note_beats_by_default() {
+        let tempo = Tempo::new(120.0);
+        assert_eq!(tempo.seconds(NoteLength::fraction(4)), 0.5);
+        assert_eq!(tempo.seconds(NoteLength::whole()), 2.0);
+        assert_eq!(
+            tempo
+                .with_beat(NoteLength::whole())
+                .seconds(NoteLength::whole()),
+            0.5
+        );
+    }
+
+    #[test]
+    fn boundaries_land_on_exact_samples() {
+        let tempo = Tempo::new(100.0);
+        let triplet = NoteLength::fraction(8).tuplet(3, 2);
+        assert_eq!(tempo.sample_at(NoteLength::ZERO, 48000.0), 0);
+        assert_eq!(tempo.sample_at(triplet, 48000.0), 9600);
+        assert_eq!(
+            tempo.sample_at(NoteLength::new(1000, 1), 48000.0),
+            115_200_000
+        );
+        assert_eq!(tempo.sample_at(NoteLength::new(1, 1000), 44100.0), 106);
+    }
+}
@@ -0,0 +1,363 @@
+//! Choosing the audio host, output device and stream configuration.
+//!
+//! Whatever an `OutputRequest` leaves open is taken from the device's
+//! default configuration. Requested values the device cannot do fall back
+//! to the nearest it can, with a note saying so, rather than failing.
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{BufferSize, SampleFormat, SupportedBufferSize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A device by its name or by its place in the list of output devices,
+/// counting from 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceChoice {
+    Name(String),
+    Index(usize),
+}
+
+impl FromStr for DeviceChoice {
+    type Err = std::convert::Infallible;
+
+    /// A plain number is an index, anything else a name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => DeviceChoice::Index(index),
+            Err(_) => DeviceChoice::Name(s.to_string()),
+        })
+    }
+}
+
+/// How to open the output stream; `None` keeps the default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutputRequest {
+    /// Audio host by name, such as `ALSA` or `JACK`.
+    pub host: Option<String>,
+    pub device: Option<DeviceChoice>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub sample_format: Option<SampleFormat>,
+    /// Frames per callback.
+    pub buffer_size: Option<u32>,
+}
+
+pub fn parse_sample_format(s: &str) -> anyhow::Result<SampleFormat> {
+    match s.to_ascii_lowercase().as_str() {
+        "f32" => Ok(SampleFormat::F32),
+        "i16" => Ok(SampleFormat::I16),
+        "u16" => Ok(SampleFormat::U16),
+        _ => Err(anyhow::Error::msg(format!(
+            "Unknown sample format '{}', expected f32, i16 or u16",
+            s
+        ))),
+    }
+}
+
+/// One of the configurations a device supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: SampleFormat,
+    /// Smallest and largest frames per callback, if the device tells.
+    pub buffer_size: Option<(u32, u32)>,
+}
+
+impl From<cpal::SupportedStreamConfigRange> for ConfigRange {
+    fn from(range: cpal::SupportedStreamConfigRange) -> Self {
+        ConfigRange {
+            channels: range.channels(),
+            min_sample_rate: range.min_sample_rate().0,
+            max_sample_rate: range.max_sample_rate().0,
+            sample_format: range.sample_format(),
+            buffer_size: match *range.buffer_size() {
+                SupportedBufferSize::Range { min, max } => Some((min, max)),
+                SupportedBufferSize::Unknown => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ConfigRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} channels, ", self.channels)?;
+        if self.min_sample_rate == self.max_sample_rate {
+            write!(f, "{} Hz", self.min_sample_rate)?;
+        } else {
+            write!(f, "{}-{} Hz", self.min_sample_rate, self.max_sample_rate)?;
+        }
+        write!(f, ", {:?}", self.sample_format)?;
+        if let Some((min, max)) = self.buffer_size {
+            write!(f, ", {}-{} frames", min, max)?;
+        }
+        Ok(())
+    }
+}
+
+/// The stream configuration to open and what had to differ from the
+/// request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Choice {
+    pub config: cpal::StreamConfig,
+    pub sample_format: SampleFormat,
+    pub fallbacks: Vec<String>,
+}
+
+/// Picks the supported configuration closest to `request`, weighing the
+/// sample rate over the channel count over the sample format. `default`
+/// fills in what the request leaves open.
+pub fn choose_config(
+    ranges: &[ConfigRange],
+    default: (u16, u32, SampleFormat),
+    request: &OutputRequest,
+) -> anyhow::Result<Choice> {
+    let channels = request.channels.unwrap_or(default.0);
+    let sample_rate = request.sample_rate.unwrap_or(default.1);
+    let sample_format = request.sample_format.unwrap_or(default.2);
+
+    let range = ranges
+        .iter()
+        .rev()
+        .max_by_key(|range| {
+            (
+                (range.min_sample_rate..=range.max_sample_rate).contains(&sample_rate),
+                range.channels == channels,
+                range.sample_format == sample_format,
+            )
+        })
+        .ok_or_else(|| anyhow::Error::msg("The output device supports no configuration"))?;
+
+    let mut fallbacks = Vec::new();
+    let chosen_rate = sample_rate.clamp(range.min_sample_rate, range.max_sample_rate);
+    if request.sample_rate.is_some() && chosen_rate != sample_rate {
+        fallbacks.push(format!(
+            "{} Hz is not supported, using {} Hz",
+            sample_rate, chosen_rate
+        ));
+    }
+    if request.channels.is_some() && range.channels != channels {
+        fallbacks.push(format!(
+            "{} channels are not supported, using {}",
+            channels, range.channels
+        ));
+    }
+    if request.sample_format.is_some() && range.sample_format != sample_format {
+        fallbacks.push(format!(
+            "{:?} samples are not supported, using {:?}",
+            sample_format, range.sample_format
+        ));
+    }
+    let buffer_size = match (request.buffer_size, range.buffer_size) {
+        (None, _) => BufferSize::Default,
+        (Some(frames), Some((min, max))) => {
+            let chosen = frames.clamp(min, max);
+            if chosen != frames {
+                fallbacks.push(format!(
+                    "A buffer of {} frames is not supported, using {}",
+                    frames, chosen
+                ));
+            }
+            BufferSize::Fixed(chosen)
+        }
+        (Some(frames), None) => {
+            fallbacks.push(format!(
+                "The device does not tell its buffer sizes, ignoring {} frames",
+                frames
+            ));
+            BufferSize::Default
+        }
+    };
+
+    Ok(Choice {
+        config: cpal::StreamConfig {
+            channels: range.channels,
+            sample_rate: cpal::SampleRate(chosen_rate),
+            buffer_size,
+        },
+        sample_format: range.sample_format,
+        fallbacks,
+    })
+}
+
+pub fn host(name: Option<&str>) -> anyhow::Result<cpal::Host> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+    let id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<&str> = cpal::available_hosts().iter().map(|id| id.name()).collect();
+            anyhow::Error::msg(format!(
+                "No audio host named '{}', available are: {}",
+                name,
+                names.join(", ")
+            ))
+        })?;
+    Ok(cpal::host_from_id(id)?)
+}
+
+pub fn output_device(
+    host: &cpal::Host,
+    choice: Option<&DeviceChoice>,
+) -> anyhow::Result<cpal::Device> {
+    let missing = |what: String| anyhow::Error::msg(format!("No output device {}", what));
+    match choice {
+        None => host
+            .default_output_device()
+            .ok_or_else(|| anyhow::Error::msg("Default output device is not available")),
+        Some(DeviceChoice::Index(index)) => host
+            .output_devices()?
+            .nth(*index)
+            .ok_or_else(|| missing(format!("number {}", index))),
+        Some(DeviceChoice::Name(name)) => host
+            .output_devices()?
+            .find(|device| device.name().is_ok_and(|other| other == *name))
+            .ok_or_else(|| missing(format!("named '{}'", name))),
+    }
+}
+
+/// Opens what `request` asks for; the `Choice` tells where it had to fall
+/// back.
+pub fn host_device_setup(
+    request: &OutputRequest,
+) -> anyhow::Result<(cpal::Host, cpal::Device, Choice)> {
+    let host = host(request.host.as_deref())?;
+    let device = output_device(&host, request.device.as_ref())?;
+
+    let default = device.default_output_config()?;
+    let ranges: Vec<ConfigRange> = device
+        .supported_output_configs()?
+        .map(ConfigRange::from)
+        .collect();
+    let choice = choose_config(
+        &ranges,
+        (
+            default.channels(),
+            default.sample_rate().0,
+            default.sample_format(),
+        ),
+        request,
+    )?;
+    Ok((host, device, choice))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges() -> Vec<ConfigRange> {
+        vec![
+            ConfigRange {
+                channels: 2,
+                min_sample_rate: 44100,
+                max_sample_rate: 48000,
+                sample_format: SampleFormat::I16,
+                buffer_size: Some((64, 4096)),
+            },
+            ConfigRange {
+                channels: 2,
+                min_sample_rate: 44100,
+                max_sample_rate: 48000,
+                sample_format: SampleFormat::F32,
+                buffer_size: Some((64, 4096)),
+            },
+            ConfigRange {
+                channels: 6,
+                min_sample_rate: 48000,
+                max_sample_rate: 96000,
+                sample_format: SampleFormat::F32,
+                buffer_size: None,
+            },
+        ]
+    }
+
+    const DEFAULT: (u16, u32, SampleFormat) = (2, 48000, SampleFormat::F32);
+
+    #[test]
+    fn defaults_fill_an_empty_request() {
+        let choice = choose_config(&ranges(), DEFAULT, &OutputRequest::default()).unwrap();
+        assert_eq!(choice.config.channels, 2);
+        assert_eq!(choice.config.sample_rate.0, 48000);
+        assert_eq!(choice.config.buffer_size, BufferSize::Default);
+        assert_eq!(choice.sample_format, SampleFormat::F32);
+        assert!(choice.fallbacks.is_empty());
+    }
+
+    #[test]
+    fn supported_requests_are_met() {
+        let request = OutputRequest {
+            sample_rate: Some(44100),
+            sample_format: Some(SampleFormat::I16),
+            buffer_size: Some(256),
+            ..OutputRequest::default()
+        };
+        let choice = choose_config(&ranges(), DEFAULT, &request).unwrap();
+        assert_eq!(choice.config.sample_rate.0, 44100);
+        assert_eq!(choice.sample_format, SampleFormat::I16);
+        assert_eq!(choice.config.buffer_size, BufferSize::Fixed(256));
+        assert!(choice.fallbacks.is_empty());
+
+        let request = OutputRequest {
+            sample_rate: Some(96000),
+            channels: Some(6),
+            ..OutputRequest::default()
+        };
+        let choice = choose_config(&ranges(), DEFAULT, &request).unwrap();
+        assert_eq!(choice.config.channels, 6);
+        assert_eq!(choice.config.sample_rate.0, 96000);
+    }
+
+    #[test]
+    fn unsupported_requests_fall_back() {
+        let request = OutputRequest {
+            sample_rate: Some(192000),
+            channels: Some(4),
+            sample_format: Some(SampleFormat::U16),
+            buffer_size: Some(16),
+            ..OutputRequest::default()
+        };
+        let choice = choose_config(&ranges()[..2], DEFAULT, &request).unwrap();
+        assert_eq!(choice.config.sample_rate.0, 48000);
+        assert_eq!(choice.config.channels, 2);
+        assert_eq!(choice.config.buffer_size, BufferSize::Fixed(64));
+        assert_eq!(choice.fallbacks.len(), 4);
+        assert_eq!(
+            choice.fallbacks[0],
+            "192000 Hz is not supported, using 48000 Hz"
+        );
+
+        let request = OutputRequest {
+            channels: Some(6),
+            buffer_size: Some(512),
+            ..OutputRequest::default()
+        };
+        let choice = choose_config(&ranges(), DEFAULT, &request).unwrap();
+        assert_eq!(choice.config.channels, 6);
+        assert_eq!(choice.config.buffer_size, BufferSize::Default);
+        assert_eq!(choice.fallbacks.len(), 1);
+
+        assert!(choose_config(&[], DEFAULT, &request).is_err());
+    }
+
+    #[test]
+    fn devices_by_name_or_index() {
+        assert_eq!("2".parse(), Ok(DeviceChoice::Index(2)));
+        assert_eq!(
+            "hw:1,0".parse(),
+            Ok(DeviceChoice::Name("hw:1,0".to_string()))
+        );
+        assert_eq!(parse_sample_format("I16").unwrap(), SampleFormat::I16);
+        assert!(parse_sample_format("f64").is_err());
+    }
+
+    #[test]
+    fn ranges_describe_themselves() {
+        assert_eq!(
+            ranges()[0].to_string(),
+            "2 channels, 44100-48000 Hz, I16, 64-4096 frames"
+        );
+    }
+}
@@ -0,0 +1,568 @@
+//! Playing songs on audio devices, into memory, or into WAV files.
+
+pub mod device;
+pub mod wav;
+
+use crate::model::looping::Loop;
+use crate::model::song::Song;
+use crate::model::tempo::Tempo;
+use crate::synthesis::synth::Stereo;
+use crate::synthesis::{on_window, sample_next, SampleRequestOptions};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::SampleFormat;
+use device::OutputRequest;
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::Duration;
+use wav::{WavSpec, WavWriter};
+
+/// Fills the windows an output sink asks for with the song, and tells when
+/// the song is over.
+pub struct Callback<F> {
+    request: SampleRequestOptions,
+    on_block: F,
+    /// Set once the song has finished to the nanoseconds the sink still
+    /// needs to play out what it was given, `NOT_FINISHED` until then. An
+    /// atomic rather than a channel, whose sender may take a lock.
+    finished: Arc<AtomicU64>,
+}
+
+const NOT_FINISHED: u64 = u64::MAX;
+
+impl<F> Callback<F>
+where
+    F: FnMut(&mut SampleRequestOptions, usize, &mut [Stereo]) + std::marker::Send + 'static + Copy,
+{
+    pub fn new(request: SampleRequestOptions, on_block: F) -> Self {
+        Callback {
+            request,
+            on_block,
+            finished: Arc::new(AtomicU64::new(NOT_FINISHED)),
+        }
+    }
+
+    /// Fills `output` with the next frames. `latency` is how long the sink
+    /// takes before it plays the first of them.
+    ///
+    /// Nothing in here allocates or locks: the song was laid out up front.
+    pub fn fill<T: cpal::Sample>(&mut self, output: &mut [T], latency: Duration) {
+        on_window(output, &mut self.request, self.on_block);
+        if self.request.is_finished() && !self.is_finished() {
+            let frames = output.len() / self.request.nchannels();
+            let buffered =
+                Duration::from_secs_f64(frames as f64 / self.request.sample_rate() as f64);
+            let remaining = (latency + buffered).as_nanos() as u64;
+            self.finished
+                .store(remaining.min(NOT_FINISHED - 1), Ordering::Release);
+        }
+    }
+
+    /// Whether the song was over by the end of the last window filled.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire) != NOT_FINISHED
+    }
+}
+
+/// Somewhere to play a song: an audio device, or memory.
+pub trait OutputSink {
+    /// Sample rate and channels the sink takes.
+    fn config(&self) -> cpal::StreamConfig;
+
+    /// Pulls windows from `callback` until the song is over.
+    fn play<F>(&mut self, callback: Callback<F>) -> anyhow::Result<()>
+    where
+        F: FnMut(&mut SampleRequestOptions, usize, &mut [Stereo])
+            + std::marker::Send
+            + 'static
+            + Copy;
+}
+
+/// Plays `song`, or `looping` over it, on `sink`, returning once it is over.
+pub fn play_on<S, F>(
+    sink: &mut S,
+    on_block: F,
+    song: Song,
+    tempo: Tempo,
+    looping: Option<Loop>,
+) -> anyhow::Result<()>
+where
+    S: OutputSink,
+    F: FnMut(&mut SampleRequestOptions, usize, &mut [Stereo]) + std::marker::Send + 'static + Copy,
+{
+    let config = sink.config();
+    let mut request =
+        SampleRequestOptions::new(config.sample_rate.0 as f32, config.channels as usize, song)
+            .with_tempo(tempo);
+    if let Some(looping) = looping {
+        request = request.with_loop(looping)?;
+    }
+    sink.play(Callback::new(request, on_block))
+}
+
+/// An audio device, through cpal.
+pub struct CpalSink {
+    pub device: cpal::Device,
+    pub config: cpal::StreamConfig,
+    pub sample_format: SampleFormat,
+    /// What the device could not do as asked, and what it does instead.
+    pub fallbacks: Vec<String>,
+}
+
+impl CpalSink {
+    /// Opens the device in the configuration `output` asks for, as far as
+    /// the device supports it.
+    pub fn open(output: &OutputRequest) -> anyhow::Result<Self> {
+        let (_host, device, choice) = device::host_device_setup(output)?;
+        Ok(CpalSink {
+            device,
+            config: choice.config,
+            sample_format: choice.sample_format,
+            fallbacks: choice.fallbacks,
+        })
+    }
+
+    fn stream<T, F>(&self, mut callback: Callback<F>) -> anyhow::Result<Playback>
+    where
+        T: cpal::Sample,
+        F: FnMut(&mut SampleRequestOptions, usize, &mut [Stereo])
+            + std::marker::Send
+            + 'static
+            + Copy,
+    {
+        let finished = callback.finished.clone();
+        // A single slot, so that reporting an error never allocates.
+        let (failed, on_failed) = mpsc::sync_channel(1);
+        let err_fn = move |err| {
+            let _ = failed.try_send(err);
+        };
+
+        let stream = self.device.build_output_stream(
+            &self.config,
+            move |output: &mut [T], info: &cpal::OutputCallbackInfo| {
+                let timestamp = info.timestamp();
+                let latency = timestamp
+                    .playback
+                    .duration_since(&timestamp.callback)
+                    .unwrap_or_default();
+                callback.fill(output, latency);
+            },
+            err_fn,
+        )?;
+
+        Ok(Playback {
+            stream,
+            finished,
+            failed: on_failed,
+        })
+    }
+}
+
+impl OutputSink for CpalSink {
+    fn config(&self) -> cpal::StreamConfig {
+        self.config.clone()
+    }
+
+    fn play<F>(&mut self, callback: Callback<F>) -> anyhow::Result<()>
+    where
+        F: FnMut(&mut SampleRequestOptions, usize, &mut [Stereo])
+            + std::marker::Send
+            + 'static
+            + Copy,
+    {
+        let playback = match self.sample_format {
+            SampleFormat::F32 => self.stream::<f32, _>(callback)?,
+            SampleFormat::I16 => self.stream::<i16, _>(callback)?,
+            SampleFormat::U16 => self.stream::<u16, _>(callback)?,
+        };
+        playback.stream.play()?;
+        Ok(playback.wait()?)
+    }
+}
+
+/// A stream playing a song, which tells when the song is over.
+struct Playback {
+    stream: cpal::Stream,
+    finished: Arc<AtomicU64>,
+    failed: Receiver<cpal::StreamError>,
+}
+
+/// How often `Playback::wait` looks whether the song is over.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl Playback {
+    /// Blocks until the release of the last note has been heard.
+    fn wait(&self) -> Result<(), cpal::StreamError> {
+        loop {
+            if let Ok(error) = self.failed.try_recv() {
+                return Err(error);
+            }
+            let remaining = self.finished.load(Ordering::Acquire);
+            if remaining != NOT_FINISHED {
+                std::thread::sleep(Duration::from_nanos(remaining));
+                return Ok(());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Samples in the format a sink took them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Samples {
+    F32(Vec<f32>),
+    I16(Vec<i16>),
+    U16(Vec<u16>),
+}
+
+impl Samples {
+    fn new(sample_format: SampleFormat) -> Self {
+        match sample_format {
+            SampleFormat::F32 => Samples::F32(vec![]),
+            SampleFormat::I16 => Samples::I16(vec![]),
+            SampleFormat::U16 => Samples::U16(vec![]),
+        }
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        match self {
+            Samples::F32(_) => SampleFormat::F32,
+            Samples::I16(_) => SampleFormat::I16,
+            Samples::U16(_) => SampleFormat::U16,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Samples::F32(samples) => samples.len(),
+            Samples::I16(samples) => samples.len(),
+            Samples::U16(samples) => samples.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Collects a song in memory instead of playing it, pulling windows of
+/// `buffer_frames` frames the way an audio device does, as fast as they
+/// come.
+#[derive(Debug, Clone)]
+pub struct MemorySink {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub buffer_frames: usize,
+    /// Stops a song that would not end, such as an endless loop, after this
+    /// many frames.
+    pub max_frames: Option<usize>,
+    /// Everything the last song played, interleaved.
+    pub output: Samples,
+}
+
+impl MemorySink {
+    pub fn new(sample_rate: u32, channels: u16, sample_format: SampleFormat) -> Self {
+        MemorySink {
+            sample_rate,
+            channels,
+            buffer_frames: 512,
+            max_frames: None,
+            output: Samples::new(sample_format),
+        }
+    }
+
+    pub fn with_buffer_frames(self, buffer_frames: usize) -> Self {
+        assert!(buffer_frames > 0, "a buffer needs at least one frame");
+        MemorySink {
+            buffer_frames,
+            ..self
+        }
+    }
+
+    pub fn with_max_frames(self, max_frames: usize) -> Self {
+        MemorySink {
+            max_frames: Some(max_frames),
+            ..self
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.output.len() / self.channels as usize
+    }
+
+    fn pull<T, F>(&self, callback: &mut Callback<F>, output: &mut Vec<T>)
+    where
+        T: cpal::Sample + Default,
+        F: FnMut(&mut SampleRequestOptions, usize, &mut [Stereo])
+            + std::marker::Send
+            + 'static
+            + Copy,
+    {
+        let channels = self.channels as usize;
+        let mut buffer = vec![T::default(); self.buffer_frames * channels];
+        while !callback.is_finished()
+            && self
+                .max_frames
+                .is_none_or(|max| output.len() / channels < max)
+        {
+            callback.fill(&mut buffer, Duration::ZERO);
+            output.extend_from_slice(&buffer);
+        }
+        if let Some(max) = self.max_frames {
+            output.truncate(max * channels);
+        }
+    }
+}
+
+impl OutputSink for MemorySink {
+    fn config(&self) -> cpal::StreamConfig {
+        cpal::StreamConfig {
+            channels: self.channels,
+            sample_rate: cpal::SampleRate(self.sample_rate),
+            buffer_size: cpal::BufferSize::Fixed(self.buffer_frames as u32),
+        }
+    }
+
+    fn play<F>(&mut self, mut callback: Callback<F>) -> anyhow::Result<()>
+    where
+        F: FnMut(&mut SampleRequestOptions, usize, &mut [Stereo])
+            + std::marker::Send
+            + 'static
+            + Copy,
+    {
+        let mut output = Samples::new(self.output.sample_format());
+        match &mut output {
+            Samples::F32(samples) => self.pull(&mut callback, samples),
+            Samples::I16(samples) => self.pull(&mut callback, samples),
+            Samples::U16(samples) => self.pull(&mut callback, samples),
+        }
+        self.output = output;
+        Ok(())
+    }
+}
+
+const RENDER_BLOCK_FRAMES: usize = 1024;
+
+/// Drives `on_block` through the same `on_window` path as the cpal
+/// callback, but into `writer` instead of an audio device.
+pub fn render<F, W>(
+    request: &mut SampleRequestOptions,
+    on_block: F,
+    frames: usize,
+    writer: &mut WavWriter<W>,
+) -> std::io::Result<()>
+where
+    F: FnMut(&mut SampleRequestOptions, usize, &mut [Stereo]) + std::marker::Send + 'static + Copy,
+    W: Write + Seek,
+{
+    let mut buffer = vec![0f32; RENDER_BLOCK_FRAMES * request.nchannels()];
+    let mut remaining = frames;
+    while remaining > 0 {
+        let block_frames = remaining.min(RENDER_BLOCK_FRAMES);
+        let block = &mut buffer[..block_frames * request.nchannels()];
+        on_window(block, request, on_block);
+        writer.write_samples(block)?;
+        remaining -= block_frames;
+    }
+    Ok(())
+}
+
+/// Renders `song`, or `looping` over it, into a WAV file at `path`,
+/// returning the number of frames written.
+pub fn render_to_wav(
+    path: &Path,
+    spec: WavSpec,
+    song: Song,
+    tempo: Tempo,
+    looping: Option<Loop>,
+) -> anyhow::Result<usize> {
+    anyhow::ensure!(
+        looping.is_none_or(|looping| looping.times.is_some()),
+        "A loop without end cannot be rendered"
+    );
+    let mut request =
+        SampleRequestOptions::new(spec.sample_rate as f32, spec.channels as usize, song)
+            .with_tempo(tempo);
+    if let Some(looping) = looping {
+        request = request.with_loop(looping)?;
+    }
+    let frames = request.duration_samples() as usize;
+
+    // Not even a header is left behind for a spec that cannot be written.
+    spec.validate()?;
+    let mut writer = WavWriter::new(BufWriter::new(File::create(path)?), spec)?;
+    render(&mut request, sample_next, frames, &mut writer)?;
+    writer.finalize()?;
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::length::NoteLength;
+    use crate::parsing::notation::parse_song;
+    use wav::WavFormat;
+
+    const LEVELS: [f32; 5] = [0.0, 0.5, -0.5, 1.0, -1.0];
+
+    /// Steps through `LEVELS` on the left and their negations on the right.
+    fn levels(request: &mut SampleRequestOptions, _track: usize, output: &mut [Stereo]) {
+        for (frame, stereo) in output.iter_mut().enumerate() {
+            let level = LEVELS[(request.sample_clock() as usize + frame) % LEVELS.len()];
+            *stereo = Stereo {
+                left: level,
+                right: -level,
+            };
+        }
+    }
+
+    fn played(
+        mut sink: MemorySink,
+        on_block: fn(&mut SampleRequestOptions, usize, &mut [Stereo]),
+        song: &str,
+    ) -> MemorySink {
+        play_on(
+            &mut sink,
+            on_block,
+            parse_song(song).unwrap(),
+            Tempo::default(),
+            None,
+        )
+        .unwrap();
+        sink
+    }
+
+    #[test]
+    fn every_sample_format_converts_exactly() {
+        // A quarter note at 120 bpm lasts 500 frames at 1 kHz, which 64
+        // frame buffers round up to 512.
+        let song = "C4/4";
+        let sink = |format| {
+            played(
+                MemorySink::new(1000, 2, format).with_buffer_frames(64),
+                levels,
+                song,
+            )
+        };
+
+        let f32_sink = sink(SampleFormat::F32);
+        assert_eq!(f32_sink.frames(), 512);
+        let expected: Vec<f32> = (0..512)
+            .flat_map(|frame| [LEVELS[frame % 5], -LEVELS[frame % 5]])
+            .collect();
+        assert_eq!(f32_sink.output, Samples::F32(expected));
+
+        let i16_levels = [0, 16383, -16384, 32767, -32768];
+        let i16_negated = [0, -16384, 16383, -32768, 32767];
+        let expected: Vec<i16> = (0..512)
+            .flat_map(|frame| [i16_levels[frame % 5], i16_negated[frame % 5]])
+            .collect();
+        assert_eq!(sink(SampleFormat::I16).output, Samples::I16(expected));
+
+        let u16_levels = [32768, 49151, 16384, 65535, 0];
+        let u16_negated = [32768, 16384, 49151, 0, 65535];
+        let expected: Vec<u16> = (0..512)
+            .flat_map(|frame| [u16_levels[frame % 5], u16_negated[frame % 5]])
+            .collect();
+        assert_eq!(sink(SampleFormat::U16).output, Samples::U16(expected));
+    }
+
+    #[test]
+    fn melodies_sound_the_same_in_every_format_and_buffer_size() {
+        let song = "track pan=-0.5
+                    C4,E4,G4/8 D4/8~ D4/4 r/8 A4/8
+                    track waveform=saw
+                    C2/4. G2/8";
+        let sink = |format, buffer_frames| {
+            played(
+                MemorySink::new(48000, 2, format).with_buffer_frames(buffer_frames),
+                sample_next,
+                song,
+            )
+        };
+
+        let reference = match sink(SampleFormat::F32, 512).output {
+            Samples::F32(samples) => samples,
+            other => panic!("{:?} instead of f32", other.sample_format()),
+        };
+        // Three quarters at 120 bpm, and whatever the releases add.
+        assert!(reference.len() >= 2 * 72000);
+        assert_eq!(reference[..2], [0.0, 0.0]);
+        assert!(reference.iter().any(|&sample| sample.abs() > 0.1));
+
+        for buffer_frames in [1, 100, 4096] {
+            let Samples::F32(samples) = sink(SampleFormat::F32, buffer_frames).output else {
+                panic!("not f32");
+            };
+            assert_eq!(samples.len() % (2 * buffer_frames), 0);
+            let shared = samples.len().min(reference.len());
+            assert_eq!(samples[..shared], reference[..shared], "{}", buffer_frames);
+            let tail = if samples.len() > shared {
+                &samples
+            } else {
+                &reference
+            };
+            assert!(tail[shared..].iter().all(|&sample| sample == 0.0));
+        }
+
+        fn converted<T: cpal::Sample>(samples: &[f32]) -> Vec<T> {
+            samples.iter().map(cpal::Sample::from).collect()
+        }
+        assert_eq!(
+            sink(SampleFormat::I16, 512).output,
+            Samples::I16(converted(&reference))
+        );
+        assert_eq!(
+            sink(SampleFormat::U16, 512).output,
+            Samples::U16(converted(&reference))
+        );
+    }
+
+    #[test]
+    fn endless_loops_stop_at_the_frame_limit() {
+        let mut sink = MemorySink::new(1000, 1, SampleFormat::I16)
+            .with_buffer_frames(100)
+            .with_max_frames(1050);
+        let looping = Loop::new(NoteLength::ZERO, NoteLength::fraction(4));
+        play_on(
+            &mut sink,
+            sample_next,
+            parse_song("A4/4").unwrap(),
+            Tempo::default(),
+            Some(looping),
+        )
+        .unwrap();
+        assert_eq!(sink.frames(), 1050);
+
+        let past_the_end = Loop::new(NoteLength::whole(), NoteLength::new(2, 1));
+        let mut sink = MemorySink::new(1000, 1, SampleFormat::I16);
+        let played = play_on(
+            &mut sink,
+            sample_next,
+            parse_song("A4/4").unwrap(),
+            Tempo::default(),
+            Some(past_the_end),
+        );
+        assert!(played.is_err());
+    }
+
+    #[test]
+    fn render_writes_requested_number_of_frames() {
+        let spec = WavSpec {
+            sample_rate: 8000,
+            channels: 2,
+            format: WavFormat::Pcm16,
+        };
+        let mut request = SampleRequestOptions::new(8000.0, 2, parse_song("A4/1 A5/1").unwrap());
+        let mut writer = WavWriter::new(std::io::Cursor::new(Vec::new()), spec).unwrap();
+        render(&mut request, sample_next, 2500, &mut writer).unwrap();
+        let bytes = writer.finalize().unwrap().into_inner();
+
+        let data_bytes = u32::from_le_bytes(bytes[42..46].try_into().unwrap());
+        assert_eq!(data_bytes, 2500 * 2 * 2);
+        assert!(bytes[46..].iter().any(|&b| b != 0));
+    }
+}
@@ -0,0 +1,282 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Sample encoding of a WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavFormat {
+    Pcm16,
+    Pcm24,
+    Float32,
+}
+
+impl WavFormat {
+    fn bytes_per_sample(self) -> u16 {
+        match self {
+            WavFormat::Pcm16 => 2,
+            WavFormat::Pcm24 => 3,
+            WavFormat::Float32 => 4,
+        }
+    }
+
+    fn format_tag(self) -> u16 {
+        match self {
+            WavFormat::Pcm16 | WavFormat::Pcm24 => 1,
+            WavFormat::Float32 => 3,
+        }
+    }
+}
+
+impl std::str::FromStr for WavFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pcm16" | "16" => Ok(WavFormat::Pcm16),
+            "pcm24" | "24" => Ok(WavFormat::Pcm24),
+            "f32" | "float" | "float32" => Ok(WavFormat::Float32),
+            _ => Err(anyhow::Error::msg(format!(
+                "Unknown WAV format '{}', expected pcm16, pcm24 or f32",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WavSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub format: WavFormat,
+}
+
+impl WavSpec {
+    /// Checks that a WAV header can describe the spec.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.channels == 0 {
+            return Err(io::Error::other("a WAV file needs at least one channel"));
+        }
+        self.byte_rate().map(drop)
+    }
+
+    fn block_align(&self) -> io::Result<u16> {
+        self.channels
+            .checked_mul(self.format.bytes_per_sample())
+            .ok_or_else(|| {
+                io::Error::other(format!(
+                    "{} channels are too many for a WAV file",
+                    self.channels
+                ))
+            })
+    }
+
+    fn byte_rate(&self) -> io::Result<u32> {
+        self.sample_rate
+            .checked_mul(self.block_align()? as u32)
+            .ok_or_else(|| {
+                io::Error::other(format!(
+                    "a sample rate of {} Hz is too high for a WAV file",
+                    self.sample_rate
+                ))
+            })
+    }
+
+    /// Most sample bytes the 32 bit chunk sizes can count, leaving room for
+    /// the other chunks and a pad byte.
+    fn max_data_bytes(&self) -> u32 {
+        let fact = match self.format {
+            WavFormat::Float32 => FACT_CHUNK_BYTES,
+            _ => 0,
+        };
+        u32::MAX - (HEADER_BYTES - 8 + FMT_CHUNK_BYTES + fact + 8) - 1
+    }
+}
+
+/// Streams interleaved `f32` samples into a RIFF/WAVE container.
+///
+/// The chunk sizes are only known once all samples are written, so the
+/// header is patched in `finalize`.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    spec: WavSpec,
+    data_bytes: u32,
+}
+
+const HEADER_BYTES: u32 = 12;
+const FMT_CHUNK_BYTES: u32 = 8 + 18;
+const FACT_CHUNK_BYTES: u32 = 8 + 4;
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, spec: WavSpec) -> io::Result<Self> {
+        spec.validate()?;
+        write_header(&mut writer, &spec, 0)?;
+        Ok(WavWriter {
+            writer,
+            spec,
+            data_bytes: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 4);
+        for &sample in samples {
+            let sample = sample.clamp(-1.0, 1.0);
+            match self.spec.format {
+                WavFormat::Pcm16 => {
+                    bytes.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes())
+                }
+                WavFormat::Pcm24 => {
+                    let value = (sample * 8_388_607.0) as i32;
+                    bytes.extend_from_slice(&value.to_le_bytes()[..3])
+                }
+                WavFormat::Float32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+        let data_bytes = u32::try_from(bytes.len())
+            .ok()
+            .and_then(|len| self.data_bytes.checked_add(len))
+            .filter(|&data_bytes| data_bytes <= self.spec.max_data_bytes())
+            .ok_or_else(|| io::Error::other("the samples do not fit into a WAV file"))?;
+        self.writer.write_all(&bytes)?;
+        self.data_bytes = data_bytes;
+        Ok(())
+    }
+
+    /// Fixes up the chunk sizes and hands back the underlying writer.
+    pub fn finalize(mut self) -> io::Result<W> {
+        if self.data_bytes % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.writer, &self.spec, self.data_bytes)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn write_header<W: Write>(writer: &mut W, spec: &WavSpec, data_bytes: u32) -> io::Result<()> {
+    let is_float = spec.format == WavFormat::Float32;
+    let padded_data = data_bytes + data_bytes % 2;
+    let riff_size = HEADER_BYTES - 8
+        + FMT_CHUNK_BYTES
+        + if is_float { FACT_CHUNK_BYTES } else { 0 }
+        + 8
+        + padded_data;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&riff_size.to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&18u32.to_le_bytes())?;
+    writer.write_all(&spec.format.format_tag().to_le_bytes())?;
+    writer.write_all(&spec.channels.to_le_bytes())?;
+    writer.write_all(&spec.sample_rate.to_le_bytes())?;
+    writer.write_all(&spec.byte_rate()?.to_le_bytes())?;
+    writer.write_all(&spec.block_align()?.to_le_bytes())?;
+    writer.write_all(&(spec.format.bytes_per_sample() * 8).to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?;
+
+    if is_float {
+        writer.write_all(b"fact")?;
+        writer.write_all(&4u32.to_le_bytes())?;
+        writer.write_all(&(data_bytes / spec.block_align()? as u32).to_le_bytes())?;
+    }
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_bytes.to_le_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn pcm16_header_and_samples() {
+        let spec = WavSpec {
+            sample_rate: 48000,
+            channels: 2,
+            format: WavFormat::Pcm16,
+        };
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+        writer.write_samples(&[0.0, 1.0, -1.0, 0.5]).unwrap();
+        let bytes = writer.finalize().unwrap().into_inner();
+
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..12], b"WAVE");
+        assert_eq!(u32_at(&bytes, 24), 48000);
+        assert_eq!(&bytes[38..42], b"data");
+        assert_eq!(u32_at(&bytes, 42), 8);
+        let samples: Vec<i16> = bytes[46..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, vec![0, i16::MAX, -i16::MAX, i16::MAX / 2]);
+    }
+
+    #[test]
+    fn pcm24_pads_odd_data_chunk() {
+        let spec = WavSpec {
+            sample_rate: 44100,
+            channels: 1,
+            format: WavFormat::Pcm24,
+        };
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+        writer.write_samples(&[-1.0]).unwrap();
+        let bytes = writer.finalize().unwrap().into_inner();
+
+        assert_eq!(u32_at(&bytes, 42), 3);
+        assert_eq!(&bytes[46..49], &[0x01, 0x00, 0x80]);
+        assert_eq!(bytes.len(), 50);
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+    }
+
+    #[test]
+    fn float32_has_fact_chunk() {
+        let spec = WavSpec {
+            sample_rate: 44100,
+            channels: 1,
+            format: WavFormat::Float32,
+        };
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), spec).unwrap();
+        writer.write_samples(&[0.25, -0.25]).unwrap();
+        let bytes = writer.finalize().unwrap().into_inner();
+
+        assert_eq!(u16::from_le_bytes([bytes[20], bytes[21]]), 3);
+        assert_eq!(&bytes[38..42], b"fact");
+        assert_eq!(u32_at(&bytes, 46), 2);
+        assert_eq!(&bytes[50..54], b"data");
+        assert_eq!(f32::from_le_bytes(bytes[58..62].try_into().unwrap()), 0.25);
+    }
+
+    #[test]
+    fn specs_and_sizes_must_fit_the_header() {
+        let spec = |sample_rate, channels| WavSpec {
+            sample_rate,
+            channels,
+            format: WavFormat::Pcm16,
+        };
+        let new = |spec| WavWriter::new(Cursor::new(Vec::new()), spec);
+        assert!(new(spec(48000, 0)).is_err());
+        assert!(new(spec(48000, 60000)).is_err());
+        assert!(new(spec(u32::MAX, 1)).is_err());
+        let error = new(spec(u32::MAX, 1)).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "a sample rate of 4294967295 Hz is too high for a WAV file"
+        );
+
+        let mut writer = new(spec(48000, 1)).unwrap();
+        writer.data_bytes = writer.spec.max_data_bytes() - 2;
+        writer.write_samples(&[0.5]).unwrap();
+        assert!(writer.write_samples(&[0.5]).is_err());
+        let bytes = writer.finalize().unwrap().into_inner();
+        assert_eq!(u32_at(&bytes, 4), u32::MAX - 1);
+    }
+}
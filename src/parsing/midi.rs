@@ -0,0 +1,1045 @@
+//! Standard MIDI Files.
+//!
+//! `write_midi` writes a song as a type 1 file, `parse_midi` reads type 0
+//! and type 1 files with a ticks-per-quarter division. Every channel of
+//! every track with notes becomes a `Track` of the song. Overlapping notes
+//! are cut into chords at every note start and end, joined by ties where a
+//! note carries on. A chord strikes all its new pitches at one velocity and
+//! holds everything across a tie, so notes struck at another velocity, or
+//! struck again while others carry on, go to a further part of the channel.
+//!
+//! MIDI gives tempo changes in time, while a song plays at one `Tempo`. The
+//! first tempo becomes that of the song and the notes under later tempos
+//! are stretched so that they keep their time. Events the song has no place
+//! for are counted and reported in `MidiImport::warnings`.
+
+use crate::model::length::{gcd, NoteLength};
+use crate::model::pitch::Pitch;
+use crate::model::song::{Song, Track};
+use crate::model::tempo::Tempo;
+use crate::model::{Melody, Note};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Microseconds per quarter note when a file sets no tempo, 120 bpm.
+const DEFAULT_TEMPO: u64 = 500_000;
+
+/// A meter change, at `position` from the start of the song.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub position: NoteLength,
+    pub numerator: u8,
+    pub denominator: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct MidiImport {
+    pub song: Song,
+    pub tempo: Tempo,
+    pub time_signatures: Vec<TimeSignature>,
+    /// What could not be carried over, e.g. `track 2: control change
+    /// events ignored (14)`.
+    pub warnings: Vec<String>,
+}
+
+pub fn load_midi(path: &Path) -> anyhow::Result<MidiImport> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| anyhow::Error::msg(format!("Cannot read {}: {}", path.display(), e)))?;
+    parse_midi(&bytes).map_err(|e| anyhow::Error::msg(format!("{}: {}", path.display(), e)))
+}
+
+pub fn parse_midi(bytes: &[u8]) -> anyhow::Result<MidiImport> {
+    let mut reader = Reader { bytes, position: 0 };
+    let mut warnings = Warnings::default();
+
+    let (id, header) = reader.chunk()?;
+    if id != *b"MThd" || header.len() < 6 {
+        return Err(anyhow::Error::msg("not a Standard MIDI File"));
+    }
+    let mut header = Reader {
+        bytes: header,
+        position: 0,
+    };
+    let format = header.u16()?;
+    let track_count = header.u16()?;
+    let division = header.u16()?;
+    if format > 1 {
+        return Err(anyhow::Error::msg(format!(
+            "MIDI file type {} is not supported, only types 0 and 1",
+            format
+        )));
+    }
+    if division & 0x8000 != 0 || division == 0 {
+        return Err(anyhow::Error::msg(
+            "only ticks per quarter note are supported as MIDI time division",
+        ));
+    }
+
+    let mut tracks = Vec::new();
+    while tracks.len() < track_count as usize {
+        let (id, data) = reader.chunk()?;
+        if id == *b"MTrk" {
+            tracks.push(read_track(data, tracks.len() + 1, &mut warnings)?);
+        } else {
+            warnings.add(0, "unknown chunks skipped");
+        }
+    }
+
+    let tempo_map = TempoMap::new(
+        division as u64,
+        tracks
+            .iter()
+            .flat_map(|track| track.tempos.iter().copied())
+            .collect(),
+    );
+    let mut time_signatures: Vec<(u64, TimeSignature)> = tracks
+        .iter()
+        .flat_map(|track| track.time_signatures.iter().copied())
+        .map(|(tick, mut signature)| {
+            signature.position = tempo_map.length(0, tick)?;
+            Ok((tick, signature))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    time_signatures.sort_by_key(|&(tick, _)| tick);
+
+    let mut song = Song::default();
+    for (number, track) in tracks.iter().enumerate() {
+        for (&channel, notes) in &track.notes {
+            let name = match (&track.name, track.notes.len()) {
+                (Some(name), 1) => name.clone(),
+                (Some(name), _) => format!("{} channel {}", name, channel + 1),
+                (None, _) => format!("track {} channel {}", number + 1, channel + 1),
+            };
+            for (part, melody) in slice_into_chords(notes, &tempo_map)?
+                .into_iter()
+                .enumerate()
+            {
+                let name = match part {
+                    0 => name.clone(),
+                    _ => format!("{} part {}", name, part + 1),
+                };
+                song.tracks.push(Track::new(name, melody));
+            }
+        }
+    }
+
+    Ok(MidiImport {
+        song,
+        tempo: tempo_map.tempo(),
+        time_signatures: time_signatures.into_iter().map(|(_, s)| s).collect(),
+        warnings: warnings.into_messages(),
+    })
+}
+
+/// A note as MIDI plays it, from `start` to `end` in ticks.
+#[derive(Debug, Clone, Copy)]
+struct MidiNote {
+    start: u64,
+    end: u64,
+    key: u8,
+    velocity: u8,
+}
+
+#[derive(Debug, Default)]
+struct MidiTrack {
+    name: Option<String>,
+    notes: BTreeMap<u8, Vec<MidiNote>>,
+    /// Microseconds per quarter note from a tick on.
+    tempos: Vec<(u64, u64)>,
+    time_signatures: Vec<(u64, TimeSignature)>,
+    /// Notes that are on, by channel and key: start tick and velocity.
+    sounding: BTreeMap<(u8, u8), (u64, u8)>,
+}
+
+impl MidiTrack {
+    /// Ends the note on `key`, if there is one.
+    fn note_off(&mut self, tick: u64, channel: u8, key: u8) -> bool {
+        let Some((start, velocity)) = self.sounding.remove(&(channel, key)) else {
+            return false;
+        };
+        self.notes.entry(channel).or_default().push(MidiNote {
+            start,
+            end: tick,
+            key,
+            velocity,
+        });
+        true
+    }
+}
+
+fn read_track(data: &[u8], number: usize, warnings: &mut Warnings) -> anyhow::Result<MidiTrack> {
+    let mut reader = Reader {
+        bytes: data,
+        position: 0,
+    };
+    let mut track = MidiTrack::default();
+    let mut tick = 0;
+    let mut running_status = None;
+
+    while !reader.at_end() {
+        tick += reader.variable_length()?;
+        let status = match reader.peek()? {
+            status if status & 0x80 != 0 => {
+                reader.u8()?;
+                status
+            }
+            _ => running_status.ok_or_else(|| reader.error("data byte without a status byte"))?,
+        };
+
+        match status {
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let channel = status & 0x0F;
+                let first = reader.u8()?;
+                let second = match status & 0xF0 {
+                    0xC0 | 0xD0 => 0,
+                    _ => reader.u8()?,
+                };
+                match (status & 0xF0, second) {
+                    (0x80, _) | (0x90, 0) => {
+                        if !track.note_off(tick, channel, first) {
+                            warnings.add(number, "note offs without a note on ignored");
+                        }
+                    }
+                    (0x90, velocity) => {
+                        // A key struck again ends the note it was playing.
+                        track.note_off(tick, channel, first);
+                        track.sounding.insert((channel, first), (tick, velocity));
+                    }
+                    (0xA0, _) => warnings.add(number, "polyphonic aftertouch events ignored"),
+                    (0xB0, _) => warnings.add(number, "control change events ignored"),
+                    (0xC0, _) => warnings.add(number, "program change events ignored"),
+                    (0xD0, _) => warnings.add(number, "channel aftertouch events ignored"),
+                    _ => warnings.add(number, "pitch bend events ignored"),
+                }
+            }
+            0xF0 | 0xF7 => {
+                running_status = None;
+                let length = reader.variable_length()?;
+                reader.bytes(length)?;
+                warnings.add(number, "system exclusive events ignored");
+            }
+            0xFF => {
+                running_status = None;
+                let kind = reader.u8()?;
+                let length = reader.variable_length()?;
+                let data = reader.bytes(length)?;
+                match (kind, data) {
+                    (0x03, name) => {
+                        track.name = Some(String::from_utf8_lossy(name).trim().to_string())
+                    }
+                    (0x2F, _) => break,
+                    (0x51, &[0, 0, 0]) => {
+                        return Err(reader.error("a tempo needs more than 0 µs per quarter note"))
+                    }
+                    (0x51, &[a, b, c]) => track
+                        .tempos
+                        .push((tick, u64::from_be_bytes([0, 0, 0, 0, 0, a, b, c]))),
+                    (0x58, &[numerator, denominator, ..]) if denominator < 8 => {
+                        track.time_signatures.push((
+                            tick,
+                            TimeSignature {
+                                position: NoteLength::ZERO,
+                                numerator,
+                                denominator: 1 << denominator,
+                            },
+                        ))
+                    }
+                    (0x01..=0x0F, _) => warnings.add(number, "text events ignored"),
+                    (0x20 | 0x21, _) => {
+                        warnings.add(number, "channel or port prefix events ignored")
+                    }
+                    (0x54, _) => warnings.add(number, "SMPTE offset events ignored"),
+                    (0x59, _) => warnings.add(number, "key signature events ignored"),
+                    (0x7F, _) => warnings.add(number, "sequencer specific events ignored"),
+                    _ => warnings.add(number, "unknown meta events ignored"),
+                }
+            }
+            _ => return Err(reader.error(&format!("unexpected status byte {:#04X}", status))),
+        }
+    }
+
+    while let Some(&(channel, key)) = track.sounding.keys().next() {
+        warnings.add(number, "notes without a note off ended with the track");
+        track.note_off(tick, channel, key);
+    }
+    Ok(track)
+}
+
+/// Cuts overlapping notes into sequences of chords, tied wherever a note
+/// sounds on past the cut: one sequence for every part the notes need.
+fn slice_into_chords(notes: &[MidiNote], tempo_map: &TempoMap) -> anyhow::Result<Vec<Melody>> {
+    let mut notes = notes.to_vec();
+    notes.sort_by_key(|note| (note.start, note.key));
+    let mut parts: Vec<Part> = Vec::new();
+    for note in notes {
+        match parts.iter_mut().find(|part| part.accepts(&note)) {
+            Some(part) => part.add(note),
+            None => parts.push(Part::new(note)),
+        }
+    }
+    parts.iter().map(|part| part.chords(tempo_map)).collect()
+}
+
+/// Notes that a single sequence of chords can play, added in order of
+/// their starts.
+struct Part {
+    notes: Vec<MidiNote>,
+    /// Start and velocity of the notes added last.
+    start: u64,
+    velocity: u8,
+    /// Latest end of the notes that start before `start`, and of all notes.
+    reach_before: u64,
+    reach: u64,
+    /// Where the last note of each key ends.
+    key_ends: [Option<u64>; 256],
+}
+
+impl Part {
+    fn new(note: MidiNote) -> Self {
+        let mut part = Part {
+            notes: Vec::new(),
+            start: note.start,
+            velocity: note.velocity,
+            reach_before: 0,
+            reach: 0,
+            key_ends: [None; 256],
+        };
+        part.add(note);
+        part
+    }
+
+    /// Whether `note` fits: struck at the velocity of the notes it starts
+    /// with, and not struck again where a tie would hold it instead.
+    fn accepts(&self, note: &MidiNote) -> bool {
+        let (reach, same_velocity) = if note.start > self.start {
+            (self.reach, true)
+        } else {
+            (self.reach_before, note.velocity == self.velocity)
+        };
+        let held = self.key_ends[note.key as usize] == Some(note.start) && reach > note.start;
+        same_velocity && !held
+    }
+
+    fn add(&mut self, note: MidiNote) {
+        if note.start > self.start {
+            self.reach_before = self.reach;
+            self.start = note.start;
+            self.velocity = note.velocity;
+        }
+        self.reach = self.reach.max(note.end);
+        self.key_ends[note.key as usize] = Some(note.end);
+        self.notes.push(note);
+    }
+
+    /// Sweeps through the notes, cutting a chord at every start and end.
+    fn chords(&self, tempo_map: &TempoMap) -> anyhow::Result<Melody> {
+        let mut cuts: Vec<u64> = self
+            .notes
+            .iter()
+            .flat_map(|note| [note.start, note.end])
+            .chain([0])
+            .collect();
+        cuts.sort_unstable();
+        cuts.dedup();
+        // Every chord, and every sum of them, is at most the whole part.
+        tempo_map.length(0, cuts[cuts.len() - 1])?;
+
+        let mut waiting = self.notes.iter().peekable();
+        let mut sounding: Vec<&MidiNote> = Vec::new();
+        let mut velocity = 0;
+        let melody = cuts
+            .windows(2)
+            .map(|cut| {
+                let (from, to) = (cut[0], cut[1]);
+                sounding.retain(|note| note.end > from);
+                while let Some(note) = waiting.next_if(|note| note.start == from) {
+                    velocity = note.velocity;
+                    sounding.push(note);
+                }
+                let mut keys: Vec<u8> = sounding.iter().map(|note| note.key).collect();
+                keys.sort_unstable();
+                let note = Note::chord(
+                    keys.into_iter().map(|key| Pitch::from_midi(key as i32)),
+                    tempo_map.length(from, to)?,
+                )
+                .with_velocity(velocity as f32 / 127.0);
+                Ok(if sounding.iter().any(|note| note.end > to) {
+                    note.tied()
+                } else {
+                    note
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Melody { melody })
+    }
+}
+
+/// Turns ticks into lengths at the first tempo of the file.
+struct TempoMap {
+    ticks_per_quarter: u64,
+    /// Microseconds per quarter note from a tick on, starting at tick 0.
+    tempos: Vec<(u64, u64)>,
+}
+
+impl TempoMap {
+    fn new(ticks_per_quarter: u64, mut tempos: Vec<(u64, u64)>) -> Self {
+        tempos.sort_by_key(|&(tick, _)| tick);
+        if tempos.first().is_none_or(|&(tick, _)| tick > 0) {
+            tempos.insert(0, (0, DEFAULT_TEMPO));
+        }
+        TempoMap {
+            ticks_per_quarter,
+            tempos,
+        }
+    }
+
+    fn tempo(&self) -> Tempo {
+        Tempo::new(60_000_000.0 / self.tempos[0].1 as f64)
+    }
+
+    /// Length from tick `from` to `to`, stretched by each tempo against the
+    /// first one.
+    fn length(&self, from: u64, to: u64) -> anyhow::Result<NoteLength> {
+        let reference = self.tempos[0].1;
+        let whole = 4 * self.ticks_per_quarter * reference;
+        self.tempos
+            .iter()
+            .enumerate()
+            .try_fold(NoteLength::ZERO, |length, (index, &(start, tempo))| {
+                let end = self.tempos.get(index + 1).map_or(u64::MAX, |&(end, _)| end);
+                let ticks = to.min(end).saturating_sub(from.max(start));
+                ticks
+                    .checked_mul(tempo)
+                    .and_then(|stretched| length.checked_add(NoteLength::new(stretched, whole)))
+            })
+            .ok_or_else(|| {
+                anyhow::Error::msg(format!(
+                    "tick {} is too far into the file to count in exact note lengths",
+                    to
+                ))
+            })
+    }
+}
+
+/// How often something was left out, by track; track 0 is the file.
+#[derive(Debug, Default)]
+struct Warnings {
+    counts: BTreeMap<(usize, &'static str), usize>,
+}
+
+impl Warnings {
+    fn add(&mut self, track: usize, kind: &'static str) {
+        *self.counts.entry((track, kind)).or_default() += 1;
+    }
+
+    fn into_messages(self) -> Vec<String> {
+        self.counts
+            .into_iter()
+            .map(|((track, what), count)| match track {
+                0 => format!("{} ({})", what, count),
+                _ => format!("track {}: {} ({})", track, what, count),
+            })
+            .collect()
+    }
+}
+
+/// Ticks per quarter note for exported files, unless the note lengths need
+/// a finer grid.
+const EXPORT_TICKS_PER_QUARTER: u64 = 480;
+
+/// The most ticks per quarter note a file header can hold.
+const MAX_TICKS_PER_QUARTER: u64 = 0x7FFF;
+
+/// Pitch bend that leaves a note in tune.
+const CENTRE_BEND: u16 = 0x2000;
+
+/// The pitch bend range players assume unless told otherwise.
+const BEND_RANGE_CENTS: f32 = 200.0;
+
+/// Channels for exported tracks, leaving out channel 10, which most players
+/// keep for drums.
+const EXPORT_CHANNELS: [u8; 15] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15];
+
+/// Writes `song` as a type 1 file: a tempo track, then every track of the
+/// song on a channel of its own, which allows for 15 tracks.
+///
+/// The ticks per quarter note are chosen so that every note starts on a
+/// whole tick, as far as the header can hold them. Cents become a pitch
+/// bend ahead of the note; as a channel bends all its notes at once, the
+/// lowest new pitch of a chord sets the bend for all of them.
+pub fn write_midi(song: &Song, tempo: Tempo) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(
+        song.tracks.len() <= EXPORT_CHANNELS.len(),
+        "A MIDI file has channels for {} tracks besides drums, the song has {}",
+        EXPORT_CHANNELS.len(),
+        song.tracks.len()
+    );
+    let ticks_per_quarter = export_ticks_per_quarter(song);
+    let mut bytes = Vec::new();
+    let mut header = Vec::new();
+    for value in [1, song.tracks.len() as u16 + 1, ticks_per_quarter as u16] {
+        header.extend(value.to_be_bytes());
+    }
+    write_chunk(&mut bytes, b"MThd", &header);
+
+    let quarter = (tempo.seconds(NoteLength::fraction(4)) * 1e6).round() as u64;
+    let mut tempo_track = TrackWriter::default();
+    tempo_track.meta(0, 0x51, &quarter.clamp(1, 0xFF_FFFF).to_be_bytes()[5..]);
+    write_chunk(&mut bytes, b"MTrk", &tempo_track.finish(0));
+
+    for (track, &channel) in song.tracks.iter().zip(&EXPORT_CHANNELS) {
+        let data = write_track(track, channel, ticks_per_quarter);
+        write_chunk(&mut bytes, b"MTrk", &data);
+    }
+    Ok(bytes)
+}
+
+pub fn save_midi(path: &Path, song: &Song, tempo: Tempo) -> anyhow::Result<()> {
+    std::fs::write(path, write_midi(song, tempo)?)
+        .map_err(|e| anyhow::Error::msg(format!("Cannot write {}: {}", path.display(), e)))
+}
+
+/// The coarsest grid of at least `EXPORT_TICKS_PER_QUARTER` that every
+/// note start falls on, or `EXPORT_TICKS_PER_QUARTER` itself with the
+/// notes rounded to it when no such grid fits into the header.
+fn export_ticks_per_quarter(song: &Song) -> u64 {
+    let mut grid: u64 = 1;
+    for track in &song.tracks {
+        let mut position = NoteLength::ZERO;
+        for note in &track.melody.melody {
+            position = position + note.length;
+            let needed = position.denominator() / gcd(position.denominator(), 4);
+            match (grid / gcd(grid, needed)).checked_mul(needed) {
+                Some(finer) if finer <= MAX_TICKS_PER_QUARTER => grid = finer,
+                _ => return EXPORT_TICKS_PER_QUARTER,
+            }
+        }
+    }
+    grid * EXPORT_TICKS_PER_QUARTER.div_ceil(grid)
+}
+
+/// The tick nearest to `position`.
+fn tick_at(position: NoteLength, ticks_per_quarter: u64) -> u64 {
+    let ticks = position.numerator() as u128 * 4 * ticks_per_quarter as u128;
+    let denominator = position.denominator() as u128;
+    ((2 * ticks + denominator) / (2 * denominator)) as u64
+}
+
+/// The MIDI key nearest to `pitch` and the pitch bend for the cents left.
+fn key_and_bend(pitch: &Pitch) -> (u8, u16) {
+    let cents = pitch.midi() as f32 * 100.0 + pitch.cents;
+    let key = (cents / 100.0).round().clamp(0.0, 127.0);
+    let bend = CENTRE_BEND as f32 * (1.0 + (cents - key * 100.0) / BEND_RANGE_CENTS);
+    (key as u8, bend.round().clamp(0.0, 0x3FFF as f32) as u16)
+}
+
+fn write_track(track: &Track, channel: u8, ticks_per_quarter: u64) -> Vec<u8> {
+    let mut writer = TrackWriter::default();
+    writer.meta(0, 0x03, track.name.as_bytes());
+
+    let mut sounding: Vec<u8> = Vec::new();
+    let mut bend = CENTRE_BEND;
+    let mut tied = false;
+    let mut position = NoteLength::ZERO;
+    for note in &track.melody.melody {
+        let tick = tick_at(position, ticks_per_quarter);
+        let mut keys: Vec<(u8, u16)> = note.pitches.iter().map(key_and_bend).collect();
+        keys.sort_unstable_by_key(|&(key, _)| key);
+        keys.dedup_by_key(|&mut (key, _)| key);
+
+        let held = |key: &u8| tied && keys.iter().any(|&(other, _)| other == *key);
+        for &key in sounding.iter().filter(|key| !held(key)) {
+            writer.event(tick, &[0x80 | channel, key, 0]);
+        }
+        sounding.retain(held);
+
+        let velocity = (note.velocity * 127.0).round().clamp(1.0, 127.0) as u8;
+        let struck: Vec<(u8, u16)> = keys
+            .into_iter()
+            .filter(|(key, _)| !sounding.contains(key))
+            .collect();
+        if let Some(&(_, lowest_bend)) = struck.first() {
+            if lowest_bend != bend {
+                bend = lowest_bend;
+                writer.event(
+                    tick,
+                    &[0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8],
+                );
+            }
+        }
+        for (key, _) in struck {
+            writer.event(tick, &[0x90 | channel, key, velocity]);
+            sounding.push(key);
+        }
+
+        tied = note.tie;
+        position = position + note.length;
+    }
+
+    let end = tick_at(position, ticks_per_quarter);
+    for &key in &sounding {
+        writer.event(end, &[0x80 | channel, key, 0]);
+    }
+    writer.finish(end)
+}
+
+/// Track data with delta times from absolute ticks.
+#[derive(Debug, Default)]
+struct TrackWriter {
+    data: Vec<u8>,
+    tick: u64,
+}
+
+impl TrackWriter {
+    fn event(&mut self, tick: u64, event: &[u8]) {
+        write_variable_length(&mut self.data, tick - self.tick);
+        self.data.extend(event);
+        self.tick = tick;
+    }
+
+    fn meta(&mut self, tick: u64, kind: u8, data: &[u8]) {
+        self.event(tick, &[0xFF, kind]);
+        write_variable_length(&mut self.data, data.len() as u64);
+        self.data.extend(data);
+    }
+
+    fn finish(mut self, tick: u64) -> Vec<u8> {
+        self.meta(tick, 0x2F, &[]);
+        self.data
+    }
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    bytes.extend(id);
+    bytes.extend((data.len() as u32).to_be_bytes());
+    bytes.extend(data);
+}
+
+/// Seven bits per byte, most significant first, the high bit set on all
+/// but the last.
+fn write_variable_length(bytes: &mut Vec<u8>, value: u64) {
+    let mut shift = 7 * ((64 - value.leading_zeros()).max(1).div_ceil(7) - 1);
+    while shift > 0 {
+        bytes.push((value >> shift) as u8 & 0x7F | 0x80);
+        shift -= 7;
+    }
+    bytes.push(value as u8 & 0x7F);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow::Error::msg(format!("byte {}: {}", self.position, message))
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn bytes(&mut self, count: u64) -> anyhow::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(count as usize)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| self.error("unexpected end of data"))?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn peek(&self) -> anyhow::Result<u8> {
+        self.bytes
+            .get(self.position)
+            .copied()
+            .ok_or_else(|| self.error("unexpected end of data"))
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    /// A number of up to four bytes, seven bits each, most significant first.
+    fn variable_length(&mut self) -> anyhow::Result<u64> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u64;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.error("variable length number longer than four bytes"))
+    }
+
+    fn chunk(&mut self) -> anyhow::Result<([u8; 4], &'a [u8])> {
+        let id = self.bytes(4)?.try_into()?;
+        let length = self.u32()?;
+        Ok((id, self.bytes(length as u64)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file of `tracks`, each a list of delta ticks and event bytes.
+    fn midi_file(format: u16, tracks: &[Vec<(u64, Vec<u8>)>]) -> Vec<u8> {
+        let mut bytes = b"MThd\0\0\0\x06".to_vec();
+        for value in [format, tracks.len() as u16, 480] {
+            bytes.extend(value.to_be_bytes());
+        }
+        for events in tracks {
+            let mut data = Vec::new();
+            for (delta, event) in events {
+                write_variable_length(&mut data, *delta);
+                data.extend(event);
+            }
+            data.extend([0x00, 0xFF, 0x2F, 0x00]);
+            write_chunk(&mut bytes, b"MTrk", &data);
+        }
+        bytes
+    }
+
+    fn notes(melody: &Melody) -> Vec<String> {
+        melody
+            .melody
+            .iter()
+            .map(|note| {
+                let pitches: Vec<String> = note.pitches.iter().map(Pitch::to_string).collect();
+                let tie = if note.tie { "~" } else { "" };
+                format!("{}/{}{}", pitches.join(","), note.length, tie)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_notes_rests_and_velocities() {
+        let bytes = midi_file(
+            0,
+            &[vec![
+                (0, vec![0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]),
+                (0, vec![0x90, 60, 127]),
+                (480, vec![0x80, 60, 0]),
+                (480, vec![0x90, 64, 64]),
+                // Running status, with a zero velocity note on as note off.
+                (960, vec![64, 0]),
+            ]],
+        );
+        let import = parse_midi(&bytes).unwrap();
+        assert_eq!(import.tempo, Tempo::new(120.0));
+        assert_eq!(import.song.tracks.len(), 1);
+        let melody = &import.song.tracks[0].melody;
+        assert_eq!(notes(melody), vec!["C4/1/4", "/1/4", "E4/1/2"]);
+        assert_eq!(melody.melody[0].velocity, 1.0);
+        assert!((melody.melody[2].velocity - 64.0 / 127.0).abs() < 1e-6);
+        assert!(import.warnings.is_empty());
+    }
+
+    #[test]
+    fn overlapping_notes_become_tied_chords() {
+        let bytes = midi_file(
+            0,
+            &[vec![
+                (0, vec![0x90, 60, 100]),
+                (480, vec![0x90, 64, 100]),
+                (480, vec![0x80, 60, 0]),
+                (0, vec![0x80, 64, 0]),
+            ]],
+        );
+        let import = parse_midi(&bytes).unwrap();
+        assert_eq!(
+            notes(&import.song.tracks[0].melody),
+            vec!["C4/1/4~", "C4,E4/1/4"]
+        );
+    }
+
+    #[test]
+    fn notes_a_chord_cannot_hold_go_to_further_parts() {
+        // A held bass under a repeated melody note, then a chord struck at
+        // two velocities.
+        let bytes = midi_file(
+            0,
+            &[vec![
+                (0, vec![0x90, 36, 100]),
+                (0, vec![0x90, 64, 100]),
+                (480, vec![0x80, 64, 0]),
+                (0, vec![0x90, 64, 100]),
+                (480, vec![0x80, 64, 0]),
+                (0, vec![0x80, 36, 0]),
+                (0, vec![0x90, 60, 100]),
+                (0, vec![0x90, 67, 50]),
+                (480, vec![0x80, 60, 0]),
+                (0, vec![0x80, 67, 0]),
+            ]],
+        );
+        let import = parse_midi(&bytes).unwrap();
+        let names: Vec<&str> = import
+            .song
+            .tracks
+            .iter()
+            .map(|track| track.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["track 1 channel 1", "track 1 channel 1 part 2"]);
+        let (first, second) = (&import.song.tracks[0].melody, &import.song.tracks[1].melody);
+        assert_eq!(notes(first), vec!["C2,E4/1/4~", "C2/1/4", "C4/1/4"]);
+        assert_eq!(notes(second), vec!["/1/4", "E4/1/4", "G4/1/4"]);
+        assert!((first.melody[2].velocity - 100.0 / 127.0).abs() < 1e-6);
+        assert!((second.melody[2].velocity - 50.0 / 127.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn later_tempos_stretch_the_notes() {
+        let bytes = midi_file(
+            0,
+            &[vec![
+                (0, vec![0x90, 60, 100]),
+                (480, vec![0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90]),
+                (0, vec![0x90, 62, 100]),
+                (0, vec![0x80, 60, 0]),
+                (480, vec![0x80, 62, 0]),
+            ]],
+        );
+        let import = parse_midi(&bytes).unwrap();
+        assert_eq!(import.tempo, Tempo::new(120.0));
+        assert_eq!(
+            notes(&import.song.tracks[0].melody),
+            vec!["C4/1/4", "D4/1/8"]
+        );
+    }
+
+    #[test]
+    fn type_one_tracks_and_channels() {
+        let tempo_track = vec![
+            (0, vec![0xFF, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08]),
+            (1920, vec![0xFF, 0x58, 0x04, 0x06, 0x03, 0x18, 0x08]),
+        ];
+        let piano = vec![
+            (0, b"\xFF\x03\x05Piano".to_vec()),
+            (0, vec![0x90, 60, 100]),
+            (0, vec![0x91, 36, 100]),
+            (1920, vec![0x80, 60, 0]),
+            (0, vec![0x81, 36, 0]),
+        ];
+        let bass = vec![(0, vec![0x92, 40, 100]), (960, vec![0x82, 40, 0])];
+        let import = parse_midi(&midi_file(1, &[tempo_track, piano, bass])).unwrap();
+
+        let names: Vec<&str> = import
+            .song
+            .tracks
+            .iter()
+            .map(|track| track.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["Piano channel 1", "Piano channel 2", "track 3 channel 3"]
+        );
+        assert_eq!(notes(&import.song.tracks[2].melody), vec!["E2/1/2"]);
+        assert_eq!(
+            import.time_signatures,
+            vec![
+                TimeSignature {
+                    position: NoteLength::ZERO,
+                    numerator: 3,
+                    denominator: 4
+                },
+                TimeSignature {
+                    position: NoteLength::whole(),
+                    numerator: 6,
+                    denominator: 8
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_events_are_reported() {
+        let bytes = midi_file(
+            0,
+            &[vec![
+                (0, vec![0xC0, 5]),
+                (0, vec![0xB0, 7, 100]),
+                (0, vec![10, 90]),
+                (0, vec![0x90, 60, 100]),
+                (480, vec![0xE0, 0, 64]),
+                (0, vec![0x80, 62, 0]),
+            ]],
+        );
+        let import = parse_midi(&bytes).unwrap();
+        assert_eq!(
+            import.warnings,
+            vec![
+                "track 1: control change events ignored (2)",
+                "track 1: note offs without a note on ignored (1)",
+                "track 1: notes without a note off ended with the track (1)",
+                "track 1: pitch bend events ignored (1)",
+                "track 1: program change events ignored (1)",
+            ]
+        );
+        assert_eq!(notes(&import.song.tracks[0].melody), vec!["C4/1/4"]);
+    }
+
+    #[test]
+    fn reject_unsupported_files() {
+        assert!(parse_midi(b"RIFF\0\0\0\x06\0\0\0\x01\x01\xE0").is_err());
+        let mut bytes = midi_file(0, &[vec![]]);
+        bytes[9] = 2;
+        assert!(parse_midi(&bytes).is_err());
+        let mut bytes = midi_file(0, &[vec![]]);
+        bytes[12] = 0xE7;
+        assert!(parse_midi(&bytes).is_err());
+        let bytes = midi_file(0, &[vec![(0, vec![0x90, 60])]]);
+        assert!(parse_midi(&bytes[..bytes.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn reject_tempos_and_lengths_that_cannot_be_counted() {
+        let bytes = midi_file(0, &[vec![(0, vec![0xFF, 0x51, 0x03, 0, 0, 0])]]);
+        let error = parse_midi(&bytes).unwrap_err();
+        assert!(error.to_string().contains("more than 0 µs"), "{}", error);
+
+        // A note held for 2^41 ticks at the slowest tempo.
+        let mut events = vec![
+            (0, vec![0xFF, 0x51, 0x03, 0xFF, 0xFF, 0xFF]),
+            (0, vec![0x90, 60, 100]),
+        ];
+        events.extend((0..8192).map(|_| (0x0FFF_FFFF, vec![0xFF, 0x01, 0x00])));
+        events.push((8192, vec![0x80, 60, 0]));
+        let error = parse_midi(&midi_file(0, &[events])).unwrap_err();
+        assert!(
+            error.to_string().contains("too far into the file"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn variable_length_numbers() {
+        for (value, expected) in [
+            (0, vec![0x00]),
+            (0x7F, vec![0x7F]),
+            (0x80, vec![0x81, 0x00]),
+            (0x2000, vec![0xC0, 0x00]),
+            (0x0FFF_FFFF, vec![0xFF, 0xFF, 0xFF, 0x7F]),
+        ] {
+            let mut bytes = Vec::new();
+            write_variable_length(&mut bytes, value);
+            assert_eq!(bytes, expected);
+            let mut reader = Reader {
+                bytes: &bytes,
+                position: 0,
+            };
+            assert_eq!(reader.variable_length().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn exported_songs_read_back() {
+        let song = crate::parsing::notation::parse_song(
+            "C4/4 r E4,G4/2~ E4,G4/8 D4~ D4/4\ntrack bass\nC3/8:3 D3 E3 F3/4. r/8",
+        )
+        .unwrap();
+        let mut song = song;
+        song.tracks[0].melody.melody[0].velocity = 0.5;
+        let bytes = write_midi(&song, Tempo::new(90.0)).unwrap();
+        let import = parse_midi(&bytes).unwrap();
+
+        assert!(import.warnings.is_empty());
+        assert!((import.tempo.bpm - 90.0).abs() < 1e-3);
+        assert_eq!(u16::from_be_bytes([bytes[12], bytes[13]]), 480);
+        assert_eq!(import.song.tracks[0].name, "melody");
+        assert_eq!(
+            notes(&import.song.tracks[0].melody),
+            vec!["C4/1/4", "/1/4", "E4,G4/5/8", "D4/3/8"]
+        );
+        assert!((import.song.tracks[0].melody.melody[0].velocity - 64.0 / 127.0).abs() < 1e-6);
+        assert_eq!(import.song.tracks[1].name, "bass");
+        assert_eq!(
+            notes(&import.song.tracks[1].melody),
+            vec!["C3/1/12", "D3/1/12", "E3/1/12", "F3/3/8"]
+        );
+    }
+
+    #[test]
+    fn partial_ties_hold_the_common_pitches() {
+        let song = Song::from(crate::parsing::notation::parse_melody("C4,E4/4~ C4,G4/4").unwrap());
+        let import = parse_midi(&write_midi(&song, Tempo::default()).unwrap()).unwrap();
+        assert_eq!(
+            notes(&import.song.tracks[0].melody),
+            vec!["C4,E4/1/4~", "C4,G4/1/4"]
+        );
+    }
+
+    #[test]
+    fn ticks_per_quarter_fit_the_tuplets() {
+        let ticks = |text: &str| {
+            let song = Song::from(crate::parsing::notation::parse_melody(text).unwrap());
+            let bytes = write_midi(&song, Tempo::default()).unwrap();
+            u16::from_be_bytes([bytes[12], bytes[13]])
+        };
+        assert_eq!(ticks("C4/4 C4/8:3"), 480);
+        assert_eq!(ticks("C4/4:7"), 483);
+        assert_eq!(ticks("C4/1024"), 512);
+        assert_eq!(ticks("C4/4:7:6 C4/4:11:10 C4/4:13:12 C4/4:17:16"), 17017);
+        assert_eq!(
+            ticks("C4/4:7:6 C4/4:11:10 C4/4:13:12 C4/4:17:16 C4/4:19:16"),
+            480
+        );
+    }
+
+    #[test]
+    fn cents_become_pitch_bends() {
+        assert_eq!(key_and_bend(&"A4".parse().unwrap()), (69, 0x2000));
+        assert_eq!(key_and_bend(&"A4+25c".parse().unwrap()), (69, 0x2400));
+        assert_eq!(key_and_bend(&"A4-150c".parse().unwrap()), (68, 0x1800));
+
+        let song = Song::from(crate::parsing::notation::parse_melody("A4+25c/4 A4/4").unwrap());
+        let bytes = write_midi(&song, Tempo::default()).unwrap();
+        let find = |event: &[u8]| bytes.windows(3).position(|window| window == event);
+        let bent = find(&[0xE0, 0x00, 0x48]).unwrap();
+        assert!(bent < find(&[0x90, 69, 127]).unwrap());
+        assert!(bent < find(&[0xE0, 0x00, 0x40]).unwrap());
+
+        // One bend for the chord, that of its lowest pitch.
+        let song = Song::from(crate::parsing::notation::parse_melody("A4+25c,E5-25c/4").unwrap());
+        let bytes = write_midi(&song, Tempo::default()).unwrap();
+        let find = |event: &[u8]| bytes.windows(3).position(|window| window == event);
+        assert!(find(&[0xE0, 0x00, 0x48]).is_some());
+        assert!(find(&[0xE0, 0x00, 0x38]).is_none());
+    }
+
+    #[test]
+    fn every_track_needs_a_channel_of_its_own() {
+        let track = |index: usize| {
+            let melody = crate::parsing::notation::parse_melody("A4/4").unwrap();
+            Track::new(format!("track {}", index), melody)
+        };
+        let song = Song::new((0..15).map(track).collect());
+        let import = parse_midi(&write_midi(&song, Tempo::default()).unwrap()).unwrap();
+        assert_eq!(import.song.tracks.len(), 15);
+        assert!(!import
+            .song
+            .tracks
+            .iter()
+            .any(|track| track.name.contains("channel 10")));
+
+        let song = Song::new((0..16).map(track).collect());
+        let error = write_midi(&song, Tempo::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "A MIDI file has channels for 15 tracks besides drums, the song has 16"
+        );
+    }
+}
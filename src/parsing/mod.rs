@@ -0,0 +1,6 @@
+//! Reading and writing songs: the text notation, MIDI files, and Scala
+//! tunings.
+
+pub mod midi;
+pub mod notation;
+pub mod scala;
@@ -0,0 +1,467 @@
+//! Plain-text melody notation.
+//!
+//! A melody is a whitespace separated list of notes such as
+//! `C4/4 D4/8. E4/2`: a pitch (see `Pitch`), then `/n` for a 1/n note or
+//! `*n` for n whole notes, followed by up to eight dots. A tuplet is
+//! marked with `:n`, which plays n notes in the time of the next lower
+//! power of two (`C4/8:3` is a triplet eighth), or with `:n:m` for n notes
+//! in the time of m. A note without a length
+//! reuses the previous one, starting from a quarter. `r` in place of the
+//! pitch is a rest and a trailing `~` ties the note into the next one, as in
+//! `C4/2~ C4/8 r/8`. Pitches joined by commas form a chord, as in
+//! `C4,E4,G4/2`. `%` starts a comment that runs to the end of the line.
+//!
+//! A song holds several such melodies, each after a `track` line (see
+//! `parse_song`).
+
+use crate::model::length::NoteLength;
+use crate::model::pitch::Pitch;
+use crate::model::song::{Song, Track};
+use crate::model::{Melody, Note};
+use crate::synthesis::instrument::Instrument;
+use crate::synthesis::oscillator::Waveform;
+use crate::synthesis::pluck::PluckedString;
+use crate::synthesis::synth::Synth;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse_melody(text: &str) -> Result<Melody, ParseError> {
+    let mut melody = Vec::new();
+    let mut length = NoteLength::fraction(4);
+    let mut end = NoteLength::ZERO;
+
+    for (line_index, line) in text.lines().enumerate() {
+        for (column, token) in tokens(code(line)) {
+            melody.push(parse_note(token, &mut length, &mut end).map_err(|message| {
+                ParseError {
+                    line: line_index + 1,
+                    column,
+                    message,
+                }
+            })?);
+        }
+    }
+
+    Ok(Melody { melody })
+}
+
+/// Parses several tracks, each starting with a line such as
+/// `track bass volume=0.8 pan=-0.5 width=0.5 channels=2,3 waveform=saw`,
+/// optionally followed by `mute` or `solo`. `instrument=pluck` plays the
+/// track on a plucked string instead of the synth. Notes before the first
+/// such line form a track named `melody`.
+pub fn parse_song(text: &str) -> Result<Song, ParseError> {
+    let mut tracks: Vec<Track> = Vec::new();
+    let mut length = NoteLength::fraction(4);
+    let mut end = NoteLength::ZERO;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let mut line_tokens = tokens(code(line)).peekable();
+        let error = |column: usize| {
+            move |message: String| ParseError {
+                line: line_index + 1,
+                column,
+                message,
+            }
+        };
+
+        if let Some(&(column, "track")) = line_tokens.peek() {
+            line_tokens.next();
+            let name = line_tokens
+                .next()
+                .map(|(_, name)| name)
+                .ok_or_else(|| error(column)("a track needs a name".to_string()))?;
+            let mut track = Track::new(name, Melody { melody: vec![] });
+            let mut sound = Sound::default();
+            for (column, option) in line_tokens {
+                parse_track_option(&mut track, &mut sound, column, option)
+                    .map_err(error(column))?;
+            }
+            track.instrument = match sound {
+                Sound {
+                    pluck: true,
+                    waveform: Some((column, _)),
+                } => {
+                    return Err(error(column)(
+                        "a waveform is only for instrument=synth".to_string(),
+                    ))
+                }
+                sound => sound.instrument(),
+            };
+            tracks.push(track);
+            length = NoteLength::fraction(4);
+            end = NoteLength::ZERO;
+            continue;
+        }
+
+        for (column, token) in line_tokens {
+            let note = parse_note(token, &mut length, &mut end).map_err(error(column))?;
+            if tracks.is_empty() {
+                tracks.push(Track::new("melody", Melody { melody: vec![] }));
+            }
+            tracks.last_mut().unwrap().melody.melody.push(note);
+        }
+    }
+
+    Ok(Song::new(tracks))
+}
+
+pub fn load_melody(path: &Path) -> anyhow::Result<Melody> {
+    parse_file(path, parse_melody)
+}
+
+pub fn load_song(path: &Path) -> anyhow::Result<Song> {
+    parse_file(path, parse_song)
+}
+
+pub(crate) fn parse_file<T>(
+    path: &Path,
+    parse: fn(&str) -> Result<T, ParseError>,
+) -> anyhow::Result<T> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::Error::msg(format!("Cannot read {}: {}", path.display(), e)))?;
+    parse(&text).map_err(|e| anyhow::Error::msg(format!("{}:{}", path.display(), e)))
+}
+
+/// The part of `line` before any comment.
+fn code(line: &str) -> &str {
+    line.split('%').next().unwrap_or_default()
+}
+
+/// Parses one note token, updating the running `length` when it has one
+/// and the `end` of the melody so far.
+fn parse_note(token: &str, length: &mut NoteLength, end: &mut NoteLength) -> Result<Note, String> {
+    let (token, tie) = match token.strip_suffix('~') {
+        Some(token) => (token, true),
+        None => (token, false),
+    };
+    let (pitch, token_length) = token.find(['/', '*']).map_or((token, None), |split| {
+        (&token[..split], Some(&token[split..]))
+    });
+
+    if let Some(token_length) = token_length {
+        *length = parse_length(token_length)?;
+    }
+    let note = if pitch.eq_ignore_ascii_case("r") {
+        if tie {
+            return Err("a rest cannot be tied".to_string());
+        }
+        Note::rest(*length)
+    } else {
+        let pitches = pitch
+            .split(',')
+            .map(str::parse::<Pitch>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{}", e))?;
+        Note::chord(pitches, *length)
+    };
+    *end = end
+        .checked_add(*length)
+        .ok_or("the melody grows too long to count in exact note lengths")?;
+    Ok(if tie { note.tied() } else { note })
+}
+
+/// The instrument a track line asks for, made once the whole line is read
+/// so that the order of the options does not matter.
+#[derive(Debug, Default)]
+struct Sound {
+    pluck: bool,
+    /// The waveform, and the column it was given at.
+    waveform: Option<(usize, Waveform)>,
+}
+
+impl Sound {
+    fn instrument(&self) -> Box<dyn Instrument> {
+        if self.pluck {
+            return Box::new(PluckedString::default());
+        }
+        let waveform = self.waveform.map(|(_, waveform)| waveform);
+        Box::new(Synth::default().with_waveform(waveform.unwrap_or_default()))
+    }
+}
+
+fn parse_track_option(
+    track: &mut Track,
+    sound: &mut Sound,
+    column: usize,
+    option: &str,
+) -> Result<(), String> {
+    let error = || format!("unsupported track option '{}'", option);
+    match option.split_once('=') {
+        None if option == "mute" => track.mute = true,
+        None if option == "solo" => track.solo = true,
+        Some(("volume", volume)) => match volume.parse::<f32>() {
+            Ok(volume) if volume >= 0.0 && volume.is_finite() => track.volume = volume,
+            _ => return Err(format!("volume '{}' must be a number of 0 or more", volume)),
+        },
+        Some(("pan", pan)) => match pan.parse() {
+            Ok(pan) if (-1.0..=1.0).contains(&pan) => track.pan = pan,
+            _ => return Err(format!("pan '{}' must lie between -1 and 1", pan)),
+        },
+        Some(("width", width)) => match width.parse() {
+            Ok(width) if (0.0..=1.0).contains(&width) => track.width = width,
+            _ => return Err(format!("width '{}' must lie between 0 and 1", width)),
+        },
+        Some(("channels", channels)) => {
+            let (left, right) = channels.split_once(',').ok_or_else(error)?;
+            track.channels = [
+                left.parse().map_err(|_| error())?,
+                right.parse().map_err(|_| error())?,
+            ];
+        }
+        Some(("instrument", "synth")) => sound.pluck = false,
+        Some(("instrument", "pluck")) => sound.pluck = true,
+        Some(("instrument", instrument)) => {
+            return Err(format!(
+                "Unknown instrument '{}', expected synth or pluck",
+                instrument
+            ))
+        }
+        Some(("waveform", waveform)) => {
+            sound.waveform = Some((column, waveform.parse().map_err(|e| format!("{}", e))?))
+        }
+        _ => return Err(error()),
+    }
+    Ok(())
+}
+
+/// Splits a line on whitespace, keeping the 1-based column of each token.
+fn tokens(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split_whitespace().map(move |token| {
+        let offset = token.as_ptr() as usize - line.as_ptr() as usize;
+        (line[..offset].chars().count() + 1, token)
+    })
+}
+
+/// More dots than anyone writes, and few enough to keep the lengths exact.
+const MAX_DOTS: usize = 8;
+
+fn parse_length(s: &str) -> Result<NoteLength, String> {
+    let error = || format!("unsupported note length '{}'", s);
+    let number = |digits: &str| match digits.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(error()),
+    };
+
+    let (value, tuplet) = match s.split_once(':') {
+        Some((value, tuplet)) => (value, Some(tuplet)),
+        None => (s, None),
+    };
+    let undotted = value.trim_end_matches('.');
+    let dots = value.len() - undotted.len();
+    if dots > MAX_DOTS {
+        return Err(format!("a note takes at most {} dots", MAX_DOTS));
+    }
+    let too_fine = || format!("note length '{}' is too fine to count exactly", s);
+
+    let mut length = match undotted.split_at(1) {
+        ("/", denominator) => NoteLength::fraction(number(denominator)?),
+        ("*", wholes) => NoteLength::new(number(wholes)?, 1),
+        _ => return Err(error()),
+    }
+    .checked_dotted(dots as u32)
+    .ok_or_else(too_fine)?;
+
+    if let Some(tuplet) = tuplet {
+        let (notes, in_time_of) = match tuplet.split_once(':') {
+            Some((notes, in_time_of)) => (number(notes)?, number(in_time_of)?),
+            None => {
+                let notes = number(tuplet)?;
+                (notes, 1 << (63 - (notes - 1).max(1).leading_zeros()))
+            }
+        };
+        length = length
+            .checked_scaled(in_time_of, notes)
+            .ok_or_else(too_fine)?;
+    }
+    Ok(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::tempo::Tempo;
+    use crate::synthesis::instrument::as_synth;
+    use crate::synthesis::oscillator::Waveform;
+
+    #[test]
+    fn parse_notes_with_lengths() {
+        let melody = parse_melody("C4/4 D4/8. E4/2.\nF#4*2 Bb3 G4/16.. A4/3").unwrap();
+        let notes: Vec<String> = melody
+            .melody
+            .iter()
+            .map(|n| format!("{}:{}", n.pitches[0], n.length))
+            .collect();
+        assert_eq!(
+            notes,
+            vec!["C4:1/4", "D4:3/16", "E4:3/4", "F#4:2/1", "Bb3:2/1", "G4:7/64", "A4:1/3"]
+        );
+    }
+
+    #[test]
+    fn parse_tuplets() {
+        let lengths = |text: &str| -> Vec<String> {
+            parse_melody(text)
+                .unwrap()
+                .melody
+                .iter()
+                .map(|n| n.length.to_string())
+                .collect()
+        };
+        assert_eq!(
+            lengths("C4/8:3 C4/4:3 C4/16:5 C4/8:6 C4/8:7"),
+            vec!["1/12", "1/6", "1/20", "1/12", "1/14"]
+        );
+        assert_eq!(lengths("C4/4:5:3 C4/8.:3"), vec!["3/20", "1/8"]);
+        assert!(parse_melody("C4/8:0").is_err());
+        assert!(parse_melody("C4/0").is_err());
+        assert!(parse_melody("C4/").is_err());
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_ignored() {
+        let full_notes = Tempo::new(1.0).with_beat(NoteLength::whole());
+        let melody = parse_melody("% intro\n\nA4/1 % the A\n  A5/1\n").unwrap();
+        assert_eq!(melody.melody.len(), 2);
+        assert_eq!(melody.pitch_at(61.0, full_notes), Some(2.0));
+    }
+
+    #[test]
+    fn parse_chords() {
+        let melody = parse_melody("C4,E4,G4/2~ C4,F4,A4 G3").unwrap();
+        let chords: Vec<Vec<String>> = melody
+            .melody
+            .iter()
+            .map(|n| n.pitches.iter().map(Pitch::to_string).collect())
+            .collect();
+        assert_eq!(
+            chords,
+            vec![vec!["C4", "E4", "G4"], vec!["C4", "F4", "A4"], vec!["G3"]]
+        );
+        assert!(melody.melody[0].tie);
+
+        let error = parse_melody("C4 C4,H4/2").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 4));
+        assert!(parse_melody("C4,/2").is_err());
+    }
+
+    #[test]
+    fn parse_rests_and_ties() {
+        let full_notes = Tempo::new(1.0).with_beat(NoteLength::whole());
+        let melody = parse_melody("A4/2~ A4/2 r/1 R").unwrap();
+        assert!(melody.melody[0].tie);
+        assert!(!melody.melody[1].tie);
+        assert!(melody.melody[2].is_rest());
+        assert!(melody.melody[3].is_rest());
+        assert_eq!(melody.pitch_at(31.0, full_notes), Some(1.0));
+        assert_eq!(melody.pitch_at(61.0, full_notes), None);
+
+        let error = parse_melody("A4/4 r/4~").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 6));
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        let error = parse_melody("C4/4 D4/4\n  E4/4 H4/4").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 8));
+        assert!(error.message.contains("H4"));
+
+        let error = parse_melody("C4/4 D4/x").unwrap_err();
+        assert_eq!(error.to_string(), "1:6: unsupported note length '/x'");
+
+        let error = parse_melody(&format!("C4/4 D4/4{}", ".".repeat(63))).unwrap_err();
+        assert_eq!(error.to_string(), "1:6: a note takes at most 8 dots");
+        assert!(parse_melody("C4/4........").is_ok());
+        let error = parse_melody("C4/4294967311:4294967291:1").unwrap_err();
+        assert_eq!(
+            error.message,
+            "note length '/4294967311:4294967291:1' is too fine to count exactly"
+        );
+        let error = parse_melody("C4/4294967311 D4/4294967291").unwrap_err();
+        assert_eq!(
+            (error.column, error.message.as_str()),
+            (
+                15,
+                "the melody grows too long to count in exact note lengths"
+            )
+        );
+    }
+
+    #[test]
+    fn parse_tracks() {
+        let song = parse_song(
+            "C5/4 D5\ntrack bass volume=0.5 pan=-0.5 waveform=saw\nC3/2 % root\n\
+             track drums mute width=0.5 channels=2,3\nC2/8\n\
+             track guitar instrument=pluck\nE2,B2,E3/2",
+        )
+        .unwrap();
+        let names: Vec<&str> = song.tracks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["melody", "bass", "drums", "guitar"]);
+        assert_eq!(song.tracks[0].melody.melody.len(), 2);
+
+        let bass = &song.tracks[1];
+        assert_eq!((bass.volume, bass.pan), (0.5, -0.5));
+        let waveform = |track: &Track| as_synth(track.instrument.as_ref()).unwrap().waveform;
+        assert_eq!(waveform(bass), Waveform::Sawtooth);
+        assert_eq!(bass.melody.melody[0].length, NoteLength::fraction(2));
+        assert!(song.tracks[2].mute);
+        assert_eq!(song.tracks[2].width, 0.5);
+        assert_eq!(song.tracks[2].channels, [2, 3]);
+        assert!(song.tracks[3]
+            .instrument
+            .describe()
+            .starts_with("plucked string"));
+        assert!(parse_song("track lead instrument=kazoo").is_err());
+
+        // The options make the same instrument in any order.
+        let synth =
+            parse_song("track lead waveform=saw instrument=pluck instrument=synth").unwrap();
+        assert_eq!(waveform(&synth.tracks[0]), Waveform::Sawtooth);
+        let error = parse_song("track lead instrument=pluck waveform=saw").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1:29: a waveform is only for instrument=synth"
+        );
+        let error = parse_song("track lead waveform=saw instrument=pluck").unwrap_err();
+        assert_eq!(error.column, 12);
+        let error = parse_song("track lead instrument=harp").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1:12: Unknown instrument 'harp', expected synth or pluck"
+        );
+
+        let error = parse_song("track bass pan=2").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 12));
+        let error = parse_song("track bass pan=0 width=1.5").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1:18: width '1.5' must lie between 0 and 1"
+        );
+        assert!(parse_song("track bass width=-0.5").is_err());
+        let error = parse_song("track bass volume=-3").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1:12: volume '-3' must be a number of 0 or more"
+        );
+        assert!(parse_song("track bass volume=NaN").is_err());
+        assert!(parse_song("track bass volume=inf").is_err());
+        assert!(parse_song("track").is_err());
+        assert!(parse_song("track bass loud").is_err());
+        assert!(parse_song("track bass channels=2").is_err());
+    }
+}
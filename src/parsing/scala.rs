@@ -0,0 +1,452 @@
+//! Scala scale (`.scl`) and keyboard mapping (`.kbm`) files.
+//!
+//! A scale lists its degrees above the first one, each either in cents
+//! (with a decimal point, `701.955`) or as a ratio (`3/2`, or `2` for
+//! `2/1`); the last degree is the period after which the scale repeats. A
+//! keyboard mapping tells which key plays which degree and which key sounds
+//! at which frequency. Lines starting with `!` are comments.
+
+use crate::model::pitch::Pitch;
+use crate::model::A_IN_HZ;
+use crate::parsing::notation::{parse_file, ParseError};
+use crate::synthesis::tuning::TuningSystem;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub description: String,
+    /// Cents of every degree after the first above the first, ending with
+    /// the period.
+    pub degrees: Vec<f64>,
+}
+
+impl Scale {
+    /// Cents of `degree` above degree 0, counting on through the periods.
+    pub fn cents(&self, degree: i32) -> f64 {
+        let size = self.degrees.len() as i32;
+        let period = self.degrees[self.degrees.len() - 1];
+        let step = degree.rem_euclid(size);
+        let above = if step == 0 {
+            0.0
+        } else {
+            self.degrees[step as usize - 1]
+        };
+        above + period * degree.div_euclid(size) as f64
+    }
+}
+
+/// Which key plays which degree of a scale.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMapping {
+    /// Keys before the mapping repeats, 0 for one degree per key.
+    pub size: usize,
+    pub first_key: i32,
+    pub last_key: i32,
+    /// Key that plays degree 0.
+    pub middle_key: i32,
+    pub reference_key: i32,
+    pub reference_frequency: f64,
+    /// Degrees that each repetition of the mapping moves up.
+    pub octave_degree: i32,
+    /// Degree of each key from `middle_key` on, `None` for a silent key.
+    pub mapping: Vec<Option<i32>>,
+}
+
+/// One degree per key, with middle C on degree 0 and A4 at 440 Hz, as Scala
+/// does without a mapping file.
+impl Default for KeyboardMapping {
+    fn default() -> Self {
+        KeyboardMapping {
+            size: 0,
+            first_key: 0,
+            last_key: 127,
+            middle_key: 60,
+            reference_key: 69,
+            reference_frequency: A_IN_HZ as f64,
+            octave_degree: 0,
+            mapping: Vec::new(),
+        }
+    }
+}
+
+impl KeyboardMapping {
+    /// The degree `key` plays, `None` for a silent key or one whose degree
+    /// does not fit into an `i32`.
+    pub fn degree(&self, key: i32) -> Option<i32> {
+        if key < self.first_key || key > self.last_key {
+            return None;
+        }
+        let offset = key.checked_sub(self.middle_key)?;
+        if self.size == 0 {
+            return Some(offset);
+        }
+        let size = i32::try_from(self.size).ok()?;
+        let degree = (*self.mapping.get(offset.rem_euclid(size) as usize)?)?;
+        self.octave_degree
+            .checked_mul(offset.div_euclid(size))?
+            .checked_add(degree)
+    }
+}
+
+/// A scale played through a keyboard mapping, which looks pitches up by
+/// their MIDI note number.
+///
+/// The concert pitch scales the reference frequency of the mapping by how
+/// far it is from 440 Hz.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalaTuning {
+    pub scale: Scale,
+    pub mapping: KeyboardMapping,
+    /// Cents of the reference key above the scale's degree 0.
+    reference_cents: f64,
+}
+
+impl ScalaTuning {
+    pub fn new(scale: Scale, mapping: KeyboardMapping) -> anyhow::Result<Self> {
+        let degree = mapping.degree(mapping.reference_key).ok_or_else(|| {
+            anyhow::Error::msg(format!(
+                "The reference key {} is not mapped to the scale",
+                mapping.reference_key
+            ))
+        })?;
+        Ok(ScalaTuning {
+            reference_cents: scale.cents(degree),
+            scale,
+            mapping,
+        })
+    }
+}
+
+impl TuningSystem for ScalaTuning {
+    fn cents_above_a4(&self, pitch: &Pitch) -> Option<f64> {
+        let degree = self.mapping.degree(pitch.midi())?;
+        let reference = 1200.0 * (self.mapping.reference_frequency / A_IN_HZ as f64).log2();
+        Some(self.scale.cents(degree) - self.reference_cents + reference)
+    }
+}
+
+pub fn parse_scale(text: &str) -> Result<Scale, ParseError> {
+    let mut lines = Lines::new(text, false);
+    let description = lines.next_line("a description")?.1.trim().to_string();
+    let count = lines.value("the number of degrees", |token| {
+        token.parse::<usize>().ok().filter(|&count| count > 0)
+    })?;
+    let degrees = (0..count)
+        .map(|_| lines.value("a degree", parse_degree))
+        .collect::<Result<_, _>>()?;
+    Ok(Scale {
+        description,
+        degrees,
+    })
+}
+
+/// The largest mapping size: one degree for every MIDI key.
+const MAX_MAPPING_SIZE: usize = 128;
+
+/// More degrees to the octave than any scale has, either way.
+const MAX_OCTAVE_DEGREE: i32 = 65536;
+
+pub fn parse_mapping(text: &str) -> Result<KeyboardMapping, ParseError> {
+    let mut lines = Lines::new(text, true);
+    let size = lines.value("the size of the mapping, up to 128", |token| {
+        token
+            .parse::<usize>()
+            .ok()
+            .filter(|&size| size <= MAX_MAPPING_SIZE)
+    })?;
+    let mut key = |what| lines.value(what, |token| token.parse::<i32>().ok());
+    let first_key = key("the first key")?;
+    let last_key = key("the last key")?;
+    let middle_key = key("the middle key")?;
+    let reference_key = key("the reference key")?;
+    let reference_frequency = lines.value("the reference frequency", |token| {
+        token.parse::<f64>().ok().filter(|&hz| hz > 0.0)
+    })?;
+    let octave_degree = lines.value("the octave degree, up to 65536 either way", |token| {
+        token
+            .parse::<i32>()
+            .ok()
+            .filter(|degree| degree.abs() <= MAX_OCTAVE_DEGREE)
+    })?;
+
+    // Keys after the last entry stay silent, as do those marked `x`.
+    let mut mapping = Vec::with_capacity(size);
+    while mapping.len() < size && !lines.is_empty() {
+        mapping.push(lines.value("a degree or 'x'", |token| match token {
+            "x" => Some(None),
+            _ => token.parse::<i32>().ok().map(Some),
+        })?);
+    }
+    mapping.resize(size, None);
+
+    Ok(KeyboardMapping {
+        size,
+        first_key,
+        last_key,
+        middle_key,
+        reference_key,
+        reference_frequency,
+        octave_degree,
+        mapping,
+    })
+}
+
+pub fn load_scale(path: &Path) -> anyhow::Result<Scale> {
+    parse_file(path, parse_scale)
+}
+
+pub fn load_mapping(path: &Path) -> anyhow::Result<KeyboardMapping> {
+    parse_file(path, parse_mapping)
+}
+
+/// The lines of a Scala file that are not comments, each holding a value.
+struct Lines<'a> {
+    lines: std::vec::IntoIter<(usize, &'a str)>,
+    /// Line number just past the end of the file.
+    end: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str, skip_blank: bool) -> Self {
+        let lines: Vec<(usize, &str)> = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line))
+            .filter(|(_, line)| !line.starts_with('!'))
+            .filter(|(_, line)| !skip_blank || !line.trim().is_empty())
+            .collect();
+        Lines {
+            lines: lines.into_iter(),
+            end: text.lines().count() + 1,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lines.len() == 0
+    }
+
+    fn next_line(&mut self, what: &str) -> Result<(usize, &'a str), ParseError> {
+        self.lines.next().ok_or_else(|| ParseError {
+            line: self.end,
+            column: 1,
+            message: format!("Expected {}, found the end of the file", what),
+        })
+    }
+
+    /// Reads the first word of the next line with `parse`; anything after
+    /// it on the line is a comment.
+    fn value<T>(&mut self, what: &str, parse: impl Fn(&str) -> Option<T>) -> Result<T, ParseError> {
+        let (line, text) = self.next_line(what)?;
+        let start = text.len() - text.trim_start().len();
+        let token = text[start..].split_whitespace().next().unwrap_or_default();
+        parse(token).ok_or_else(|| ParseError {
+            line,
+            column: start + 1,
+            message: if token.is_empty() {
+                format!("Expected {}", what)
+            } else {
+                format!("Expected {}, found '{}'", what, token)
+            },
+        })
+    }
+}
+
+/// Cents with a decimal point, otherwise a ratio such as `3/2` or `2`.
+fn parse_degree(token: &str) -> Option<f64> {
+    if token.contains('.') {
+        return token.parse().ok();
+    }
+    let (numerator, denominator) = token.split_once('/').unwrap_or((token, "1"));
+    let numerator: u64 = numerator.parse().ok()?;
+    let denominator: u64 = denominator.parse().ok()?;
+    (numerator > 0 && denominator > 0)
+        .then(|| 1200.0 * (numerator as f64 / denominator as f64).log2())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthesis::tuning::{EqualTemperament, Tuning};
+
+    const TWELVE_TET: &str = "! 12tet.scl
+!
+12 tone equal temperament
+12
+!
+ 100.0
+ 200.0
+ 300.0
+ 400.0
+ 500.0
+ 600.0
+ 700.0
+ 800.0
+ 900.0
+ 1000.0
+ 1100.0
+ 2/1
+";
+
+    const BOHLEN_PIERCE: &str = "! bohlen-pierce.scl
+!
+Bohlen-Pierce scale, just intonation
+ 13
+!
+ 27/25
+ 25/21
+ 9/7
+ 7/5
+ 75/49
+ 5/3
+ 9/5
+ 49/25
+ 15/7
+ 7/3
+ 63/25
+ 25/9
+ 3/1  ! the tritave
+";
+
+    const JUST_MAJOR: &str = "! ji_7.scl
+Just major scale
+7
+9/8
+5/4
+4/3
+3/2
+5/3
+15/8
+2
+";
+
+    const WHITE_KEYS: &str = "! white.kbm
+! Size of map, first and last key, middle key
+12
+0
+127
+60
+! Reference key and frequency, octave degree
+69
+440.0
+7
+! Mapping
+0
+x
+1
+x
+2
+3
+x
+4
+x
+5
+x
+6
+";
+
+    fn frequency(tuning: &Tuning, name: &str) -> Option<f32> {
+        tuning.frequency(&name.parse().unwrap())
+    }
+
+    #[test]
+    fn twelve_tone_equal_temperament_matches_the_builtin_one() {
+        let scale = parse_scale(TWELVE_TET).unwrap();
+        assert_eq!(scale.description, "12 tone equal temperament");
+        assert_eq!(scale.degrees.len(), 12);
+        let scala = ScalaTuning::new(scale, KeyboardMapping::default()).unwrap();
+        for key in 0..128 {
+            let pitch = Pitch::from_midi(key);
+            let cents = scala.cents_above_a4(&pitch).unwrap();
+            assert!((cents - EqualTemperament.cents_above_a4(&pitch).unwrap()).abs() < 1e-9);
+        }
+        assert_eq!(scala.cents_above_a4(&Pitch::from_midi(128)), None);
+    }
+
+    #[test]
+    fn scales_repeat_at_their_period() {
+        let scale = parse_scale(BOHLEN_PIERCE).unwrap();
+        assert!((scale.degrees[12] - 1200.0 * 3f64.log2()).abs() < 1e-9);
+        let tuning = Tuning::new(ScalaTuning::new(scale, KeyboardMapping::default()).unwrap());
+
+        let a4 = frequency(&tuning, "A4").unwrap();
+        assert!((a4 - 440.0).abs() < 1e-3);
+        let tritave_up = tuning.frequency(&Pitch::from_midi(69 + 13)).unwrap();
+        assert!((tritave_up / a4 - 3.0).abs() < 1e-5);
+        let middle_c = frequency(&tuning, "C4").unwrap();
+        let fifth_degree = tuning.frequency(&Pitch::from_midi(65)).unwrap();
+        assert!((fifth_degree / middle_c - 75.0 / 49.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn mappings_leave_keys_silent() {
+        let mapping = parse_mapping(WHITE_KEYS).unwrap();
+        assert_eq!(mapping.size, 12);
+        assert_eq!(mapping.mapping[1], None);
+        assert_eq!(mapping.degree(72), Some(7));
+        assert_eq!(mapping.degree(59), Some(6 - 7));
+
+        let scale = parse_scale(JUST_MAJOR).unwrap();
+        let tuning =
+            Tuning::new(ScalaTuning::new(scale, mapping).unwrap()).with_concert_pitch(415.0);
+        assert!((frequency(&tuning, "A4").unwrap() - 415.0).abs() < 1e-3);
+        assert!((frequency(&tuning, "C4").unwrap() - 415.0 * 3.0 / 5.0).abs() < 1e-3);
+        assert!((frequency(&tuning, "E5").unwrap() - 415.0 * 3.0 / 5.0 * 2.5).abs() < 1e-3);
+        assert_eq!(frequency(&tuning, "F#4"), None);
+
+        let mut unmapped = parse_mapping(WHITE_KEYS).unwrap();
+        unmapped.reference_key = 70;
+        assert!(ScalaTuning::new(parse_scale(JUST_MAJOR).unwrap(), unmapped).is_err());
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let error = parse_scale("broken\n3\n9/8\n  4:3\n2/1\n").unwrap_err();
+        assert_eq!(error.to_string(), "4:3: Expected a degree, found '4:3'");
+
+        let error = parse_scale("! cut short\nshort\n3\n9/8\n").unwrap_err();
+        assert_eq!(error.line, 5);
+        assert_eq!(
+            error.message,
+            "Expected a degree, found the end of the file"
+        );
+
+        assert_eq!(parse_scale("zero\n0\n").unwrap_err().line, 2);
+        assert_eq!(parse_scale("negative\n1\n-3/2\n").unwrap_err().line, 3);
+
+        let error = parse_mapping(&WHITE_KEYS.replace("440.0", "loud")).unwrap_err();
+        assert_eq!(error.line, 9);
+        let error = parse_mapping(&WHITE_KEYS.replace("\nx\n4", "\ny\n4")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "18:1: Expected a degree or 'x', found 'y'"
+        );
+        let error = parse_mapping(&WHITE_KEYS.replace("\n12\n", "\n1000000000000\n")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "3:1: Expected the size of the mapping, up to 128, found '1000000000000'"
+        );
+        assert!(parse_mapping(&WHITE_KEYS.replace("\n12\n", "\n128\n")).is_ok());
+        let huge = KeyboardMapping {
+            size: 1 << 32,
+            ..KeyboardMapping::default()
+        };
+        assert_eq!(huge.degree(60), None);
+
+        let error = parse_mapping(&WHITE_KEYS.replace("\n7\n", "\n2147483647\n")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "10:1: Expected the octave degree, up to 65536 either way, found '2147483647'"
+        );
+        let far = KeyboardMapping {
+            octave_degree: i32::MAX,
+            ..parse_mapping(WHITE_KEYS).unwrap()
+        };
+        assert_eq!(far.degree(60), Some(0));
+        assert_eq!(far.degree(127), None);
+        let far = KeyboardMapping {
+            middle_key: i32::MIN,
+            ..far
+        };
+        assert_eq!(far.degree(60), None);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::model::tempo::Seconds;
+
+/// Attack, decay and release times plus the sustain level of a note.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    pub attack: Seconds,
+    pub decay: Seconds,
+    /// Level held after the decay, between 0 and 1.
+    pub sustain: f32,
+    pub release: Seconds,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Envelope {
+            attack: 0.01,
+            decay: 0.1,
+            sustain: 0.7,
+            release: 0.1,
+        }
+    }
+}
+
+impl Envelope {
+    /// Samples a release takes to fade out from full level.
+    pub fn release_samples(&self, sample_rate: f32) -> u64 {
+        (self.release * sample_rate as f64).ceil() as u64 + 1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// Where one note currently is within its `Envelope`.
+#[derive(Debug, Clone, Copy)]
+pub struct EnvelopeState {
+    stage: Stage,
+    level: f32,
+    release_step: f32,
+}
+
+impl Default for EnvelopeState {
+    fn default() -> Self {
+        EnvelopeState {
+            stage: Stage::Idle,
+            level: 0.0,
+            release_step: 0.0,
+        }
+    }
+}
+
+/// Level change per sample for a linear segment from 0 to 1 over `time`.
+fn step(time: Seconds, sample_rate: f32) -> f32 {
+    if time > 0.0 {
+        (1.0 / (time * sample_rate as f64)) as f32
+    } else {
+        1.0
+    }
+}
+
+impl EnvelopeState {
+    /// Starts the attack from the current level, so a retriggered note
+    /// does not jump back to silence.
+    pub fn note_on(&mut self) {
+        self.stage = Stage::Attack;
+    }
+
+    pub fn note_off(&mut self, envelope: &Envelope, sample_rate: f32) {
+        if self.stage != Stage::Idle {
+            self.stage = Stage::Release;
+            self.release_step = self.level * step(envelope.release, sample_rate);
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.stage == Stage::Idle
+    }
+
+    pub fn is_releasing(&self) -> bool {
+        self.stage == Stage::Release
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Returns the current level and advances by one sample.
+    pub fn next(&mut self, envelope: &Envelope, sample_rate: f32) -> f32 {
+        let value = self.level;
+        match self.stage {
+            Stage::Idle | Stage::Sustain => {}
+            Stage::Attack => {
+                self.level += step(envelope.attack, sample_rate);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level -= (1.0 - envelope.sustain) * step(envelope.decay, sample_rate);
+                if self.level <= envelope.sustain {
+                    self.level = envelope.sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Release => {
+                self.level -= self.release_step;
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = Stage::Idle;
+                }
+            }
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENVELOPE: Envelope = Envelope {
+        attack: 0.01,
+        decay: 0.01,
+        sustain: 0.5,
+        release: 0.02,
+    };
+
+    #[test]
+    fn runs_through_all_stages() {
+        let mut state = EnvelopeState::default();
+        state.note_on();
+        let levels: Vec<f32> = (0..400).map(|_| state.next(&ENVELOPE, 1000.0)).collect();
+        assert_eq!(levels[0], 0.0);
+        assert!((levels[10] - 1.0).abs() < 1e-5);
+        assert!((levels[20] - 0.5).abs() < 1e-5);
+        assert_eq!(levels[399], 0.5);
+
+        state.note_off(&ENVELOPE, 1000.0);
+        let levels: Vec<f32> = (0..21).map(|_| state.next(&ENVELOPE, 1000.0)).collect();
+        assert_eq!(levels[0], 0.5);
+        assert!(levels.windows(2).all(|pair| pair[1] < pair[0]));
+        assert!(levels[20] < 1e-5);
+        assert!(state.is_idle());
+    }
+
+    #[test]
+    fn release_during_attack_starts_from_current_level() {
+        let mut state = EnvelopeState::default();
+        state.note_on();
+        for _ in 0..5 {
+            state.next(&ENVELOPE, 1000.0);
+        }
+        state.note_off(&ENVELOPE, 1000.0);
+        assert!((state.next(&ENVELOPE, 1000.0) - 0.5).abs() < 1e-5);
+        assert!(state.is_releasing());
+    }
+}
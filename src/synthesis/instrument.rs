@@ -0,0 +1,220 @@
+use crate::model::pitch::Pitch;
+use crate::model::{AbsoluteFrequency, Note};
+use crate::synthesis::synth::Stereo;
+#[cfg(test)]
+use crate::synthesis::synth::Synth;
+use crate::synthesis::tuning::Tuning;
+use std::any::Any;
+use std::fmt;
+
+/// What a track is played with: the built-in `Synth`, or any sound of
+/// your own.
+///
+/// Playback tells it which pitches start and stop and pulls its sound a
+/// block at a time, all from the audio callback, so none of it should
+/// allocate or lock.
+pub trait Instrument: fmt::Debug + Send + InstrumentClone {
+    /// Starts `pitch` sounding at `frequency`, as hard as `velocity` from
+    /// 0 to 1, at `pan` from -1 (left) to 1 (right).
+    fn note_on(
+        &mut self,
+        pitch: Pitch,
+        frequency: AbsoluteFrequency,
+        velocity: f32,
+        pan: f32,
+        sample_rate: f32,
+    );
+
+    /// Lets `pitch` fade out.
+    fn note_off(&mut self, pitch: Pitch, sample_rate: f32);
+
+    /// Fills `output` with the next frames.
+    fn render(&mut self, output: &mut [Stereo], sample_rate: f32);
+
+    /// Whether nothing sounds any more, fading notes included.
+    fn is_idle(&self) -> bool;
+
+    /// Samples a note goes on sounding after its note off at most.
+    fn release_samples(&self, sample_rate: f32) -> u64;
+
+    /// What `notes info` calls it.
+    fn describe(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+/// Clones a boxed `Instrument` and lets it be downcast to the type it was
+/// made from; every instrument that is `Clone` has it.
+pub trait InstrumentClone {
+    fn clone_box(&self) -> Box<dyn Instrument>;
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Instrument + Clone + 'static> InstrumentClone for T {
+    fn clone_box(&self) -> Box<dyn Instrument> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Lets tests look into the built-in synth.
+#[cfg(test)]
+pub(crate) fn as_synth(instrument: &dyn Instrument) -> Option<&Synth> {
+    instrument.as_any().downcast_ref()
+}
+
+impl Clone for Box<dyn Instrument> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Moves `instrument` from the chord of note `from` to that of `to`.
+///
+/// When `from` is tied, its pitches that appear again in `to` keep
+/// sounding; everything else of `from` stops. Each pitch of `to` sits at
+/// `pan`, moved by `width` times its place in the chord.
+pub fn change_chord(
+    instrument: &mut dyn Instrument,
+    from: Option<&Note>,
+    to: Option<&Note>,
+    tuning: &Tuning,
+    pan: f32,
+    width: f32,
+    sample_rate: f32,
+) {
+    let tied = |pitch: &Pitch| {
+        from.is_some_and(|from| from.tie && from.pitches.contains(pitch))
+            && to.is_some_and(|to| to.pitches.contains(pitch))
+    };
+    for pitch in from.map_or(&[][..], |from| &from.pitches) {
+        if !tied(pitch) {
+            instrument.note_off(*pitch, sample_rate);
+        }
+    }
+
+    let Some(to) = to else {
+        return;
+    };
+    for &pitch in to.pitches.iter().filter(|pitch| !tied(pitch)) {
+        // Pitches the tuning leaves out stay silent.
+        let Some(frequency) = tuning.frequency(&pitch) else {
+            continue;
+        };
+        let pan = pan + width * spread(&to.pitches, pitch);
+        instrument.note_on(pitch, frequency, to.velocity, pan, sample_rate);
+    }
+}
+
+/// Where `pitch` falls in `pitches`, from -1 for the lowest to 1 for the
+/// highest; a single pitch sits in the middle.
+fn spread(pitches: &[Pitch], pitch: Pitch) -> f32 {
+    if pitches.len() < 2 {
+        return 0.0;
+    }
+    let rank = pitches
+        .iter()
+        .filter(|other| other.relative_to_a() < pitch.relative_to_a())
+        .count();
+    2.0 * rank as f32 / (pitches.len() - 1) as f32 - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::length::ToneLength;
+
+    /// Writes down what it is told instead of making a sound.
+    #[derive(Debug, Clone, Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl Instrument for Recorder {
+        fn note_on(
+            &mut self,
+            pitch: Pitch,
+            frequency: AbsoluteFrequency,
+            _velocity: f32,
+            pan: f32,
+            _sample_rate: f32,
+        ) {
+            self.events
+                .push(format!("on {} {:.0} {}", pitch, frequency, pan));
+        }
+
+        fn note_off(&mut self, pitch: Pitch, _sample_rate: f32) {
+            self.events.push(format!("off {}", pitch));
+        }
+
+        fn render(&mut self, output: &mut [Stereo], _sample_rate: f32) {
+            output.fill(Stereo::default());
+        }
+
+        fn is_idle(&self) -> bool {
+            true
+        }
+
+        fn release_samples(&self, _sample_rate: f32) -> u64 {
+            0
+        }
+    }
+
+    fn chord(names: &[&str]) -> Note {
+        let pitches = names.iter().map(|name| name.parse::<Pitch>().unwrap());
+        Note::chord(pitches, ToneLength::Full)
+    }
+
+    fn events(from: Option<&Note>, to: Option<&Note>) -> Vec<String> {
+        let mut recorder = Recorder::default();
+        change_chord(
+            &mut recorder,
+            from,
+            to,
+            &Tuning::default(),
+            0.0,
+            1.0,
+            48000.0,
+        );
+        recorder.events
+    }
+
+    #[test]
+    fn chords_start_and_stop_every_pitch() {
+        let c_major = chord(&["G4", "C4", "E4"]);
+        assert_eq!(
+            events(None, Some(&c_major)),
+            ["on G4 392 1", "on C4 262 -1", "on E4 330 0"]
+        );
+        assert_eq!(events(Some(&c_major), None), ["off G4", "off C4", "off E4"]);
+        // Without a tie a repeated pitch is struck again.
+        assert_eq!(
+            events(Some(&chord(&["A4"])), Some(&chord(&["A4"]))),
+            ["off A4", "on A4 440 0"]
+        );
+    }
+
+    #[test]
+    fn tied_pitches_keep_sounding() {
+        let from = chord(&["C4", "E4"]).tied();
+        assert_eq!(
+            events(Some(&from), Some(&chord(&["C4", "F4"]))),
+            ["off E4", "on F4 349 1"]
+        );
+        assert_eq!(events(Some(&from), None), ["off C4", "off E4"]);
+    }
+
+    #[test]
+    fn boxed_instruments_clone_their_state() {
+        let mut recorder: Box<dyn Instrument> = Box::new(Recorder::default());
+        recorder.note_off("A4".parse().unwrap(), 48000.0);
+        let copy = recorder.clone();
+        recorder.note_off("B4".parse().unwrap(), 48000.0);
+        assert_eq!(format!("{:?}", copy), r#"Recorder { events: ["off A4"] }"#);
+        assert_eq!(copy.describe(), format!("{:?}", copy));
+    }
+}
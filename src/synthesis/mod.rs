@@ -0,0 +1,825 @@
+//! Turning a song into samples.
+
+pub mod envelope;
+pub mod instrument;
+pub mod oscillator;
+pub mod pluck;
+pub mod synth;
+pub mod timeline;
+pub mod tuning;
+
+use crate::model::looping::Loop;
+use crate::model::song::{Song, Track};
+use crate::model::tempo::Tempo;
+use instrument::Instrument;
+use synth::Stereo;
+use timeline::Timeline;
+
+/// Fills `output` with the frames of `track` to play next.
+pub fn sample_next(o: &mut SampleRequestOptions, track: usize, output: &mut [Stereo]) {
+    o.tone(track, output)
+}
+
+/// A song laid out for playback. What the layout depends on, the sample
+/// rate, channels, tempo, loop and song, is set up front; it and the
+/// position of playback are read through getters.
+pub struct SampleRequestOptions {
+    sample_rate: f32,
+    /// Position of the next sample since playback started.
+    sample_clock: u64,
+    nchannels: usize,
+    tempo: Tempo,
+    /// Plays a stretch of the song over and over instead of the song once.
+    looping: Option<Loop>,
+    tracks: Vec<TrackState>,
+    /// The sample after the last note of the song or the last pass of the
+    /// loop, `None` for a loop that never ends.
+    end_sample: Option<u64>,
+    song: Song,
+}
+
+/// Where playback of one track of the song stands.
+#[derive(Debug, Clone)]
+struct TrackState {
+    instrument: Box<dyn Instrument>,
+    timeline: Timeline,
+    /// Pass through the loop and index of the sounding note.
+    current_note: Option<(usize, usize)>,
+    /// Index of the note played last: the sounding note or one tied on
+    /// from it, whose tie decides what carries on into the next.
+    played: usize,
+    /// The frames of the track rendered for the window being filled.
+    block: Vec<Stereo>,
+}
+
+/// Frames rendered at a time; longer windows take several blocks.
+const BLOCK_FRAMES: usize = 512;
+
+impl SampleRequestOptions {
+    /// Accepts a `Song` or a single `Melody`, which becomes a one track song.
+    pub fn new(sample_rate: f32, nchannels: usize, song: impl Into<Song>) -> Self {
+        let song = song.into();
+        SampleRequestOptions {
+            sample_rate,
+            sample_clock: 0,
+            nchannels,
+            tempo: Tempo::default(),
+            looping: None,
+            tracks: Vec::new(),
+            end_sample: None,
+            song,
+        }
+        .laid_out()
+    }
+
+    pub fn with_tempo(self, tempo: Tempo) -> Self {
+        SampleRequestOptions { tempo, ..self }.laid_out()
+    }
+
+    /// Loops `looping`, ending it at the end of the song at the latest, or
+    /// fails when the song is over before the loop would start.
+    pub fn with_loop(self, looping: Loop) -> anyhow::Result<Self> {
+        let length = self.song.length();
+        anyhow::ensure!(
+            looping.start < length,
+            "The loop starts at {}, where the song has already ended",
+            looping.start
+        );
+        let looping = Loop {
+            end: looping.end.min(length),
+            ..looping
+        };
+        Ok(SampleRequestOptions {
+            looping: Some(looping),
+            ..self
+        }
+        .laid_out())
+    }
+
+    /// Works out, before playback starts, everything the audio callback
+    /// would otherwise have to allocate or recount.
+    fn laid_out(mut self) -> Self {
+        self.tracks = self
+            .song
+            .tracks
+            .iter()
+            .map(|track| TrackState {
+                instrument: track.instrument.clone(),
+                timeline: Timeline::new(&track.melody, self.tempo, self.sample_rate),
+                current_note: None,
+                played: 0,
+                block: vec![Stereo::default(); BLOCK_FRAMES],
+            })
+            .collect();
+        self.end_sample = match &self.looping {
+            None => Some(self.tempo.sample_at(self.song.length(), self.sample_rate)),
+            Some(looping) => looping.duration_samples(self.tempo, self.sample_rate),
+        };
+        self
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Position of the next sample since playback started.
+    pub fn sample_clock(&self) -> u64 {
+        self.sample_clock
+    }
+
+    pub fn nchannels(&self) -> usize {
+        self.nchannels
+    }
+
+    pub fn tempo(&self) -> Tempo {
+        self.tempo
+    }
+
+    pub fn looping(&self) -> Option<Loop> {
+        self.looping
+    }
+
+    pub fn song(&self) -> &Song {
+        &self.song
+    }
+
+    /// Samples until the last note of every track has faded out, or
+    /// `u64::MAX` for a loop that never ends.
+    pub fn duration_samples(&self) -> u64 {
+        let release = self
+            .song
+            .tracks
+            .iter()
+            .map(|track| track.instrument.release_samples(self.sample_rate))
+            .max()
+            .unwrap_or(0);
+        self.end_sample
+            .map_or(u64::MAX, |end| end.saturating_add(release))
+    }
+
+    /// What plays `track`, with the notes it has been given so far.
+    pub fn instrument(&self, track: usize) -> &dyn Instrument {
+        self.tracks[track].instrument.as_ref()
+    }
+
+    /// Renders `track` from the sample clock on into `output`, a block at a
+    /// time between the note changes.
+    fn tone(&mut self, track: usize, output: &mut [Stereo]) {
+        let mut rendered = 0;
+        for frame in 0..output.len() {
+            let clock = self.sample_clock + frame as u64;
+            let position = match &self.looping {
+                None => Some((0, clock)),
+                Some(looping) => looping.sample(clock, self.tempo, self.sample_rate),
+            };
+            let timeline = &mut self.tracks[track].timeline;
+            let played =
+                position.and_then(|(pass, sample)| Some((pass, timeline.index_at(sample)?)));
+            let note = played.and_then(|(pass, index)| Some((pass, timeline.strike(index)?)));
+            if note != self.tracks[track].current_note {
+                self.render(track, &mut output[rendered..frame]);
+                rendered = frame;
+                self.change_note(track, note);
+            }
+            if let Some((_, index)) = played {
+                self.tracks[track].played = index;
+            }
+        }
+        self.render(track, &mut output[rendered..]);
+    }
+
+    fn render(&mut self, track: usize, output: &mut [Stereo]) {
+        if !output.is_empty() {
+            self.tracks[track]
+                .instrument
+                .render(output, self.sample_rate);
+        }
+    }
+
+    /// Stops the current note of `track` and starts `note`, given by its
+    /// pass through the loop and its index.
+    ///
+    /// Coming back to a note in the next pass counts as a change too, so
+    /// the note is struck again.
+    fn change_note(&mut self, track: usize, note: Option<(usize, usize)>) {
+        let Track {
+            melody, pan, width, ..
+        } = &self.song.tracks[track];
+        let state = &mut self.tracks[track];
+        let notes = &melody.melody;
+        instrument::change_chord(
+            state.instrument.as_mut(),
+            state.current_note.map(|_| &notes[state.played]),
+            note.map(|(_, index)| &notes[index]),
+            &self.song.tuning,
+            *pan,
+            *width,
+            self.sample_rate,
+        );
+        state.current_note = note;
+    }
+
+    /// Whether every note has been played and has faded out. From then on
+    /// the song stays silent.
+    pub fn is_finished(&self) -> bool {
+        self.end_sample.is_some_and(|end| self.sample_clock >= end)
+            && self.tracks.iter().all(|track| track.instrument.is_idle())
+    }
+}
+
+/// Channels beyond these stay silent.
+const MAX_CHANNELS: usize = 32;
+
+pub(crate) fn on_window<T, F>(output: &mut [T], request: &mut SampleRequestOptions, mut on_block: F)
+where
+    T: cpal::Sample,
+    F: FnMut(&mut SampleRequestOptions, usize, &mut [Stereo]) + std::marker::Send + 'static,
+{
+    let nchannels = request.nchannels;
+    let channels = nchannels.min(MAX_CHANNELS);
+    for window in output.chunks_mut(BLOCK_FRAMES * nchannels) {
+        let frames = window.len().div_ceil(nchannels);
+        // Silent tracks keep running so that unmuting picks up in time.
+        for index in 0..request.tracks.len() {
+            let mut block = std::mem::take(&mut request.tracks[index].block);
+            on_block(request, index, &mut block[..frames]);
+            request.tracks[index].block = block;
+        }
+
+        for (position, frame) in window.chunks_mut(nchannels).enumerate() {
+            let mut mix = [0f32; MAX_CHANNELS];
+            for (index, state) in request.tracks.iter().enumerate() {
+                if !request.song.is_audible(index) {
+                    continue;
+                }
+                let track = &request.song.tracks[index];
+                let sample = state.block[position].scaled(track.volume);
+                if channels == 1 {
+                    mix[0] += sample.mono();
+                } else {
+                    // Channels the device does not have wrap around onto those it has.
+                    mix[track.channels[0] % channels] += sample.left;
+                    mix[track.channels[1] % channels] += sample.right;
+                }
+            }
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = cpal::Sample::from::<f32>(mix.get(channel).unwrap_or(&0.0));
+            }
+        }
+        request.sample_clock += frames as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::length::{NoteLength, ToneLength};
+    use crate::model::pitch::{Letter, Pitch};
+    use crate::model::{AbsoluteFrequency, Melody, Note};
+    use crate::parsing::notation;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use synth::Voices;
+
+    fn note(pitch: &str, length: impl Into<NoteLength>) -> Note {
+        Note::new(pitch.parse::<Pitch>().unwrap(), length)
+    }
+
+    fn demo_melody() -> Melody {
+        Melody {
+            melody: vec![
+                Note::new(Pitch::new(Letter::A, 0, 4), ToneLength::Full),
+                Note::new(Pitch::new(Letter::A, 0, 5), ToneLength::Full),
+            ],
+        }
+    }
+
+    fn one_full_note_per_minute() -> Tempo {
+        Tempo::new(1.0).with_beat(NoteLength::whole())
+    }
+
+    /// Counts the allocations of each thread, so that tests running in
+    /// parallel do not see each other's.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations_during(f: impl FnOnce()) -> usize {
+        let before = ALLOCATIONS.with(Cell::get);
+        f();
+        ALLOCATIONS.with(Cell::get) - before
+    }
+
+    /// The voices of the built-in synth playing `track`.
+    fn voices(request: &SampleRequestOptions, track: usize) -> &Voices {
+        instrument::as_synth(request.instrument(track))
+            .unwrap()
+            .voices()
+    }
+
+    /// The frame of `track` at the sample clock.
+    fn tone(request: &mut SampleRequestOptions, track: usize) -> Stereo {
+        let mut frame = [Stereo::default()];
+        request.tone(track, &mut frame);
+        frame[0]
+    }
+
+    fn render_mono(request: &mut SampleRequestOptions, frames: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; frames * request.nchannels];
+        on_window(&mut samples, request, sample_next);
+        samples
+    }
+
+    #[test]
+    fn rests_are_silent() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", ToneLength::Full),
+                Note::rest(ToneLength::Full),
+                note("A5", ToneLength::Full),
+            ],
+        };
+        assert_eq!(my_melody.beat_to_note(NoteLength::new(1, 2)), Some(0));
+        assert_eq!(my_melody.beat_to_note(NoteLength::new(3, 2)), None);
+        assert_eq!(my_melody.pitch_at(61.0, one_full_note_per_minute()), None);
+        assert_eq!(
+            my_melody.pitch_at(121.0, one_full_note_per_minute()),
+            Some(2.0)
+        );
+
+        let mut request =
+            SampleRequestOptions::new(100.0, 1, my_melody).with_tempo(one_full_note_per_minute());
+        request.sample_clock = 6000;
+        assert_eq!(tone(&mut request, 0), Stereo::default());
+    }
+
+    #[test]
+    fn note_boundaries_resolve_to_exact_samples() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", NoteLength::fraction(8).tuplet(3, 2)),
+                note("A5", ToneLength::Quarter),
+            ],
+        };
+        let mut timeline = Timeline::new(&my_melody, Tempo::new(100.0), 48000.0);
+        assert_eq!(timeline.note_at(9599), Some(0));
+        assert_eq!(timeline.note_at(9600), Some(1));
+        assert_eq!(timeline.note_at(9600 + 28799), Some(1));
+        assert_eq!(timeline.note_at(9600 + 28800), None);
+    }
+
+    #[test]
+    fn sample_clock_keeps_counting() {
+        let mut request = SampleRequestOptions::new(48000.0, 1, demo_melody());
+        render_mono(&mut request, 100_000);
+        assert_eq!(request.sample_clock, 100_000);
+    }
+
+    #[test]
+    fn pitch_changes_do_not_click() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", ToneLength::Quarter),
+                note("E5", ToneLength::Quarter),
+            ],
+        };
+        let mut request = SampleRequestOptions::new(48000.0, 1, my_melody);
+        let frames = request.duration_samples() as usize;
+        let samples = render_mono(&mut request, frames);
+
+        // While A4 releases and E5 starts, both voices move at once.
+        let max_step =
+            2.0 * std::f32::consts::PI * (440.0 + Pitch::from_midi(76).frequency()) / 48000.0;
+        assert!(samples
+            .windows(2)
+            .all(|pair| (pair[1] - pair[0]).abs() <= max_step * 1.05));
+    }
+
+    #[test]
+    fn notes_fade_in_and_out() {
+        let my_melody = Melody {
+            melody: vec![note("A4", ToneLength::Quarter)],
+        };
+        let mut request = SampleRequestOptions::new(48000.0, 1, my_melody);
+        let frames = request.duration_samples() as usize;
+        let samples = render_mono(&mut request, frames);
+
+        assert!(samples[0].abs() < 1e-3);
+        assert!(samples[1].abs() < 1e-3);
+        assert!(samples[frames - 2].abs() < 1e-3);
+        assert!(samples[frames - 1].abs() < 1e-3);
+        assert!(samples.iter().any(|sample| sample.abs() > 0.5));
+        assert!(request.instrument(0).is_idle());
+    }
+
+    #[test]
+    fn release_tails_overlap_the_next_note() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", ToneLength::Quarter),
+                note("E5", ToneLength::Quarter),
+            ],
+        };
+        let mut request = SampleRequestOptions::new(48000.0, 1, my_melody);
+        render_mono(&mut request, 24000 + 100);
+        let sounding = voices(&request, 0)
+            .iter()
+            .filter(|voice| !voice.is_idle())
+            .count();
+        assert_eq!(sounding, 2);
+    }
+
+    #[test]
+    fn ties_do_not_retrigger_the_envelope() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", ToneLength::Quarter).tied(),
+                note("A4", ToneLength::Quarter),
+            ],
+        };
+        let mut request = SampleRequestOptions::new(48000.0, 1, my_melody);
+        render_mono(&mut request, 24000 + 100);
+        let sounding = voices(&request, 0)
+            .iter()
+            .filter(|voice| !voice.is_idle())
+            .count();
+        assert_eq!(sounding, 1);
+    }
+
+    #[test]
+    fn notes_after_a_tie_are_struck_again() {
+        let my_melody = notation::parse_melody("A4/4~ A4/4 A4/4").unwrap();
+        let mut request = SampleRequestOptions::new(48000.0, 1, my_melody);
+        render_mono(&mut request, 2 * 24000 + 100);
+        assert_eq!(sounding_notes(&request), (vec!["A4".to_string()], 2));
+
+        let my_melody = notation::parse_melody("C4,E4/4~ C4,E4/4 C4,G4/4").unwrap();
+        let mut request = SampleRequestOptions::new(48000.0, 1, my_melody);
+        render_mono(&mut request, 2 * 24000 + 100);
+        let (mut held, sounding) = sounding_notes(&request);
+        held.sort();
+        assert_eq!(
+            (held, sounding),
+            (vec!["C4".to_string(), "G4".to_string()], 4)
+        );
+    }
+
+    #[test]
+    fn chords_sound_all_their_pitches() {
+        let chord: Vec<Pitch> = ["C4", "E4", "G4"]
+            .iter()
+            .map(|p| p.parse().unwrap())
+            .collect();
+        let my_melody = Melody {
+            melody: vec![
+                Note::chord(chord.clone(), ToneLength::Full),
+                Note::rest(ToneLength::Full),
+            ],
+        };
+        let tempo = Tempo::default();
+        assert_eq!(my_melody.pitches_at(1.0, tempo), &chord[..]);
+        assert_eq!(
+            my_melody.pitch_at(1.0, tempo),
+            Some(chord[0].relative_to_a())
+        );
+        assert!(my_melody.pitches_at(3.0, tempo).is_empty());
+
+        let mut request = SampleRequestOptions::new(48000.0, 1, my_melody);
+        let samples = render_mono(&mut request, 48000);
+        assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+        let sounding = voices(&request, 0)
+            .iter()
+            .filter(|voice| !voice.is_idle())
+            .count();
+        assert_eq!(sounding, 3);
+    }
+
+    #[test]
+    fn tracks_are_mixed_with_volume_and_pan() {
+        let melody = Melody {
+            melody: vec![note("A4", ToneLength::Quarter)],
+        };
+        let song = |left: Track, right: Track| {
+            SampleRequestOptions::new(48000.0, 2, Song::new(vec![left, right]))
+        };
+        let peaks = |request: &mut SampleRequestOptions| {
+            let samples = render_mono(request, 24000);
+            let peak = |channel: usize| {
+                samples
+                    .iter()
+                    .skip(channel)
+                    .step_by(2)
+                    .fold(0f32, |peak, sample| peak.max(sample.abs()))
+            };
+            (peak(0), peak(1))
+        };
+
+        let mut request = song(
+            Track::new("melody", melody.clone()).with_pan(-1.0),
+            Track::new("bass", melody.clone())
+                .with_pan(1.0)
+                .with_volume(0.5),
+        );
+        let (left, right) = peaks(&mut request);
+        assert!(left > 0.5);
+        assert!((right - left * 0.5).abs() < 1e-3);
+
+        let mut request = song(
+            Track::new("melody", melody.clone()).with_pan(-1.0),
+            Track::new("bass", melody.clone()).with_pan(1.0),
+        );
+        request.song.tracks[1].solo = true;
+        let (left, right) = peaks(&mut request);
+        assert!(left < 1e-6 && right > 0.5);
+        // The silent track still follows the song.
+        assert!(!request.instrument(0).is_idle());
+    }
+
+    /// Holds a constant level, which tells the frequency and velocity it
+    /// was struck with, until the note off.
+    #[derive(Debug, Clone, Default)]
+    struct Drone {
+        level: f32,
+        /// Shared by all clones, so that a test can count the blocks.
+        renders: Arc<AtomicUsize>,
+    }
+
+    impl Instrument for Drone {
+        fn note_on(
+            &mut self,
+            _pitch: Pitch,
+            frequency: AbsoluteFrequency,
+            velocity: f32,
+            _pan: f32,
+            _sample_rate: f32,
+        ) {
+            self.level = velocity * frequency.sqrt() / 100.0;
+        }
+
+        fn note_off(&mut self, _pitch: Pitch, _sample_rate: f32) {
+            self.level = 0.0;
+        }
+
+        fn render(&mut self, output: &mut [Stereo], _sample_rate: f32) {
+            self.renders.fetch_add(1, Ordering::Relaxed);
+            output.fill(Stereo {
+                left: self.level,
+                right: self.level,
+            });
+        }
+
+        fn is_idle(&self) -> bool {
+            self.level == 0.0
+        }
+
+        fn release_samples(&self, _sample_rate: f32) -> u64 {
+            0
+        }
+    }
+
+    #[test]
+    fn tracks_play_their_own_instruments() {
+        let melody = Melody::new()
+            .with_note(note("A4", ToneLength::Quarter).with_velocity(0.5))
+            .with_note(note("A5", ToneLength::Quarter));
+        let song = Song::new(vec![
+            Track::new("drone", melody.clone()).with_instrument(Drone::default()),
+            Track::new("synth", melody),
+        ]);
+        let mut request = SampleRequestOptions::new(48000.0, 1, song.clone());
+        assert_eq!(request.duration_samples(), 48000 + 4801);
+        let crawling = SampleRequestOptions::new(48000.0, 1, song).with_tempo(Tempo::new(1e-300));
+        assert_eq!(crawling.duration_samples(), u64::MAX);
+
+        assert_eq!(tone(&mut request, 0).left, 0.5 * 440f32.sqrt() / 100.0);
+        request.sample_clock = 24000;
+        assert_eq!(tone(&mut request, 0).left, 880f32.sqrt() / 100.0);
+        request.sample_clock = 48000;
+        assert_eq!(tone(&mut request, 0), Stereo::default());
+        assert!(request.instrument(0).is_idle());
+        assert_eq!(request.song.tracks[1].instrument.describe(), "Sine");
+    }
+
+    #[test]
+    fn instruments_render_blocks_between_note_changes() {
+        // A quarter note lasts 500 frames, and blocks are 512 frames long.
+        let melody = Melody::new()
+            .with_note(note("A4", ToneLength::Quarter))
+            .with_note(note("A5", ToneLength::Quarter));
+        let drone = Drone::default();
+        let renders = drone.renders.clone();
+        let track = Track::new("drone", melody).with_instrument(drone);
+        let mut request = SampleRequestOptions::new(1000.0, 1, Song::new(vec![track]));
+        let samples = render_mono(&mut request, 1000);
+        assert_eq!(renders.load(Ordering::Relaxed), 3);
+        let level = |frequency: f32| {
+            let level = frequency.sqrt() / 100.0;
+            Stereo {
+                left: level,
+                right: level,
+            }
+            .mono()
+        };
+        assert_eq!(samples[499], level(440.0));
+        assert_eq!(samples[500], level(880.0));
+    }
+
+    #[test]
+    fn tracks_are_routed_to_their_channels() {
+        let melody = Melody {
+            melody: vec![note("A4", ToneLength::Quarter)],
+        };
+        let song = Song::new(vec![
+            Track::new("melody", melody.clone()),
+            Track::new("bass", melody).with_channels(2, 3),
+        ]);
+        let peaks = |nchannels: usize| {
+            let mut request = SampleRequestOptions::new(48000.0, nchannels, song.clone());
+            let samples = render_mono(&mut request, 24000);
+            (0..nchannels)
+                .map(|channel| {
+                    samples
+                        .iter()
+                        .skip(channel)
+                        .step_by(nchannels)
+                        .fold(0f32, |peak, sample| peak.max(sample.abs()))
+                })
+                .collect::<Vec<f32>>()
+        };
+
+        let surround = peaks(6);
+        assert!((surround[0] - surround[2]).abs() < 1e-6);
+        assert!(surround[0] > 0.5 && surround[3] > 0.5);
+        assert!(surround[4] == 0.0 && surround[5] == 0.0);
+
+        let stereo = peaks(2);
+        assert!((stereo[0] - 2.0 * surround[0]).abs() < 1e-5);
+    }
+
+    /// Pitches held by the voices of the first track, and how many voices
+    /// sound at all, releasing ones included.
+    fn sounding_notes(request: &SampleRequestOptions) -> (Vec<String>, usize) {
+        let voices = voices(request, 0);
+        let held = voices
+            .iter()
+            .filter_map(|voice| Some(voice.held()?.to_string()))
+            .collect();
+        (held, voices.iter().filter(|voice| !voice.is_idle()).count())
+    }
+
+    #[test]
+    fn loops_strike_their_first_note_again_without_a_gap() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", ToneLength::Quarter),
+                note("C5", ToneLength::Quarter),
+                note("E5", ToneLength::Half),
+            ],
+        };
+        let looping = Loop::new(NoteLength::fraction(4), NoteLength::whole()).with_times(2);
+        let mut request = SampleRequestOptions::new(48000.0, 1, my_melody)
+            .with_loop(looping)
+            .unwrap();
+        let release = request.song.tracks[0].instrument.release_samples(48000.0);
+        assert_eq!(request.duration_samples(), 2 * 72000 + release);
+
+        render_mono(&mut request, 1);
+        assert_eq!(sounding_notes(&request), (vec!["C5".to_string()], 1));
+        render_mono(&mut request, 72000 - 1);
+        assert_eq!(sounding_notes(&request), (vec!["E5".to_string()], 1));
+
+        // The last note of the first pass fades while the loop starts over.
+        let seam = render_mono(&mut request, 1);
+        assert_eq!(sounding_notes(&request), (vec!["C5".to_string()], 2));
+        assert!(seam[0].abs() > 0.0);
+
+        render_mono(&mut request, 72000 - 2);
+        assert!(!request.is_finished());
+        render_mono(&mut request, 1 + release as usize);
+        assert!(request.is_finished());
+    }
+
+    #[test]
+    fn ties_hold_across_the_loop_seam() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", ToneLength::Half),
+                note("A4", ToneLength::Half).tied(),
+            ],
+        };
+        let looping = Loop::new(NoteLength::fraction(2), NoteLength::whole());
+        let request = SampleRequestOptions::new(48000.0, 1, my_melody.clone());
+        let past_the_end = Loop::new(NoteLength::whole(), NoteLength::new(2, 1));
+        let error = request.with_loop(past_the_end).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "The loop starts at 1/1, where the song has already ended"
+        );
+        let mut request = SampleRequestOptions::new(48000.0, 1, my_melody)
+            .with_loop(looping)
+            .unwrap();
+        render_mono(&mut request, 1);
+        assert_eq!(sounding_notes(&request), (vec!["A4".to_string()], 1));
+        render_mono(&mut request, 3 * 48000);
+        assert_eq!(sounding_notes(&request), (vec!["A4".to_string()], 1));
+        assert_eq!(request.duration_samples(), u64::MAX);
+        assert!(!request.is_finished());
+    }
+
+    #[test]
+    fn render_windows_never_allocate() {
+        let melody = notation::parse_song(
+            "track lead pan=-0.5 width=1 waveform=saw
+             C4,E4,G4/8 D4/8~ D4/4 r/8 A4+12c/8 F#4,A4,C#5/2
+             track bass channels=2,3
+             C2/2 G2/4. r/16 G2/16
+             track guitar instrument=pluck
+             E2,B2,E3,G#3/8 r/8 E2,B2,E3,G#3/4~ E2,B2,E3/4 A2/4",
+        )
+        .unwrap()
+        .with_tuning(tuning::Tuning::new(tuning::RegularTemperament::meantone()));
+        let looping = Loop::new(NoteLength::fraction(4), NoteLength::whole());
+        let mut request = SampleRequestOptions::new(48000.0, 4, melody)
+            .with_loop(looping)
+            .unwrap();
+        let mut window = vec![0f32; 512 * 4];
+        let mut i16_window = vec![0i16; 512 * 4];
+
+        // Several passes through the loop, with every note change and seam.
+        let allocations = allocations_during(|| {
+            for _ in 0..1000 {
+                on_window(&mut window, &mut request, sample_next);
+                on_window(&mut i16_window, &mut request, sample_next);
+                let _ = request.is_finished();
+            }
+        });
+        assert_eq!(allocations, 0);
+        assert!(request.sample_clock > 10 * 48000);
+        assert!(allocations_during(|| drop(vec![1u8])) > 0);
+    }
+
+    #[test]
+    fn playback_finishes_when_the_last_release_ends() {
+        let my_melody = Melody {
+            melody: vec![
+                note("A4", ToneLength::Quarter),
+                Note::rest(ToneLength::Half),
+            ],
+        };
+        let mut request = SampleRequestOptions::new(48000.0, 1, my_melody);
+        render_mono(&mut request, 24000 + 100);
+        assert!(!request.is_finished());
+
+        // The release ends long before the rest does.
+        render_mono(&mut request, 48000 - 100 - 1);
+        assert!(!request.is_finished());
+        render_mono(&mut request, 1);
+        assert!(request.is_finished());
+    }
+
+    #[test]
+    fn playing_past_the_end_stays_silent() {
+        let my_melody = Melody {
+            melody: vec![note("A4", ToneLength::Quarter)],
+        };
+        let mut request = SampleRequestOptions::new(48000.0, 2, my_melody);
+        let frames = request.duration_samples() as usize;
+        render_mono(&mut request, frames);
+        assert!(request.is_finished());
+        assert!(render_mono(&mut request, 48000)
+            .iter()
+            .all(|&sample| sample == 0.0));
+
+        let mut request = SampleRequestOptions::new(48000.0, 2, Melody { melody: vec![] });
+        assert!(request.is_finished());
+        assert!(render_mono(&mut request, 100)
+            .iter()
+            .all(|&sample| sample == 0.0));
+        let mut request = SampleRequestOptions::new(48000.0, 2, Song::default());
+        assert!(request.is_finished());
+        render_mono(&mut request, 100);
+    }
+}
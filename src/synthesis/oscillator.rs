@@ -0,0 +1,220 @@
+use crate::model::AbsoluteFrequency;
+
+/// The shape of one oscillator cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Square,
+    Sawtooth,
+    Triangle,
+    /// A square wave that is high for `width` of each cycle.
+    Pulse(f32),
+    Noise,
+}
+
+impl std::str::FromStr for Waveform {
+    type Err = anyhow::Error;
+
+    /// Accepts `sine`, `square`, `saw`, `triangle`, `noise` and `pulse:<width>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let waveform = match s {
+            "sine" => Waveform::Sine,
+            "square" => Waveform::Square,
+            "saw" | "sawtooth" => Waveform::Sawtooth,
+            "triangle" => Waveform::Triangle,
+            "noise" => Waveform::Noise,
+            _ => match s.strip_prefix("pulse:").map(str::parse::<f32>) {
+                Some(Ok(width)) if width > 0.0 && width < 1.0 => Waveform::Pulse(width),
+                _ => {
+                    return Err(anyhow::Error::msg(format!(
+                        "Unknown waveform '{}', expected sine, square, saw, triangle, \
+                         pulse:<width between 0 and 1> or noise",
+                        s
+                    )))
+                }
+            },
+        };
+        Ok(waveform)
+    }
+}
+
+/// An oscillator driven by a phase accumulator.
+///
+/// The phase only ever advances by the current frequency, so changing the
+/// frequency between two samples bends the waveform instead of jumping to a
+/// different point of it. Gliding or wobbling frequencies work the same way.
+#[derive(Debug, Clone, Copy)]
+pub struct Oscillator {
+    /// Position within the current cycle, in `[0, 1)`.
+    phase: f32,
+    noise: u32,
+}
+
+impl Default for Oscillator {
+    fn default() -> Self {
+        Oscillator {
+            phase: 0.0,
+            noise: 0x9E37_79B9,
+        }
+    }
+}
+
+/// Smooths the step of a discontinuity at phase 0 over one sample on
+/// either side, which removes most of the aliasing of naive square and
+/// sawtooth waves.
+fn poly_blep(phase: f32, phase_step: f32) -> f32 {
+    if phase < phase_step {
+        let t = phase / phase_step;
+        t + t - t * t - 1.0
+    } else if phase > 1.0 - phase_step {
+        let t = (phase - 1.0) / phase_step;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+impl Oscillator {
+    pub fn next(
+        &mut self,
+        waveform: Waveform,
+        frequency: AbsoluteFrequency,
+        sample_rate: f32,
+    ) -> f32 {
+        let phase = self.phase;
+        let phase_step = (frequency / sample_rate).min(0.5);
+        let value = match waveform {
+            Waveform::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0 - poly_blep(phase, phase_step),
+            Waveform::Square => self.pulse(0.5, phase_step),
+            Waveform::Pulse(width) => self.pulse(width, phase_step),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Noise => {
+                self.noise ^= self.noise << 13;
+                self.noise ^= self.noise >> 17;
+                self.noise ^= self.noise << 5;
+                self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+            }
+        };
+        self.phase = (phase + frequency / sample_rate).fract();
+        value
+    }
+
+    fn pulse(&self, width: f32, phase_step: f32) -> f32 {
+        let naive = if self.phase < width { 1.0 } else { -1.0 };
+        naive + poly_blep(self.phase, phase_step)
+            - poly_blep((self.phase + 1.0 - width).fract(), phase_step)
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Magnitude of `frequency` in `samples`, normalised to the amplitude
+    /// of a sine.
+    fn magnitude(samples: &[f32], frequency: f32, sample_rate: f32) -> f32 {
+        let (re, im) = samples
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (n, x)| {
+                let angle = 2.0 * std::f32::consts::PI * frequency * n as f32 / sample_rate;
+                (re + x * angle.cos(), im - x * angle.sin())
+            });
+        2.0 * (re * re + im * im).sqrt() / samples.len() as f32
+    }
+
+    fn render(waveform: Waveform, frequency: f32, frames: usize) -> Vec<f32> {
+        let mut oscillator = Oscillator::default();
+        (0..frames)
+            .map(|_| oscillator.next(waveform, frequency, 48000.0))
+            .collect()
+    }
+
+    #[test]
+    fn sine_starts_at_zero_and_completes_cycles() {
+        let mut oscillator = Oscillator::default();
+        let samples: Vec<f32> = (0..8)
+            .map(|_| oscillator.next(Waveform::Sine, 1.0, 4.0))
+            .collect();
+        let expected = [0.0, 1.0, 0.0, -1.0, 0.0, 1.0, 0.0, -1.0];
+        for (sample, expected) in samples.iter().zip(expected) {
+            assert!((sample - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn frequency_changes_keep_the_phase() {
+        let sample_rate = 48000.0;
+        let mut oscillator = Oscillator::default();
+        let mut previous = oscillator.next(Waveform::Sine, 440.0, sample_rate);
+        for n in 1..4800 {
+            let frequency = if n < 2400 { 440.0 } else { 660.0 };
+            let sample = oscillator.next(Waveform::Sine, frequency, sample_rate);
+            let max_step = 2.0 * std::f32::consts::PI * frequency / sample_rate;
+            assert!((sample - previous).abs() <= max_step * 1.01);
+            previous = sample;
+        }
+    }
+
+    #[test]
+    fn waveforms_stay_in_range() {
+        for waveform in [
+            Waveform::Square,
+            Waveform::Sawtooth,
+            Waveform::Triangle,
+            Waveform::Pulse(0.1),
+            Waveform::Noise,
+        ] {
+            let samples = render(waveform, 1000.0, 4800);
+            assert!(samples.iter().all(|x| x.abs() <= 1.0), "{:?}", waveform);
+            assert!(samples.iter().any(|&x| x > 0.5), "{:?}", waveform);
+            assert!(samples.iter().any(|&x| x < -0.5), "{:?}", waveform);
+        }
+    }
+
+    #[test]
+    fn pulse_width_sets_the_duty_cycle() {
+        let samples = render(Waveform::Pulse(0.25), 480.0, 4800);
+        let high = samples.iter().filter(|&&x| x > 0.0).count();
+        assert!((high as f32 / 4800.0 - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn band_limited_sawtooth_barely_aliases() {
+        // The 14th harmonic of 3520 Hz, 49280 Hz, folds back to 1280 Hz.
+        let band_limited = render(Waveform::Sawtooth, 3520.0, 4800);
+        let naive: Vec<f32> = (0..4800)
+            .map(|n| 2.0 * (n as f32 * 3520.0 / 48000.0).fract() - 1.0)
+            .collect();
+
+        let fundamental = magnitude(&band_limited, 3520.0, 48000.0);
+        let alias = magnitude(&band_limited, 1280.0, 48000.0);
+        assert!(fundamental > 0.5);
+        assert!(alias < magnitude(&naive, 1280.0, 48000.0) / 4.0);
+    }
+
+    #[test]
+    fn band_limited_square_barely_aliases() {
+        // The 13th harmonic of 3520 Hz, 45760 Hz, folds back to 2240 Hz.
+        let band_limited = render(Waveform::Square, 3520.0, 4800);
+        let naive: Vec<f32> = (0..4800)
+            .map(|n| {
+                if (n as f32 * 3520.0 / 48000.0).fract() < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            })
+            .collect();
+
+        assert!(
+            magnitude(&band_limited, 2240.0, 48000.0) < magnitude(&naive, 2240.0, 48000.0) / 4.0
+        );
+    }
+}
@@ -0,0 +1,460 @@
+use crate::model::pitch::Pitch;
+use crate::model::tempo::Seconds;
+use crate::model::AbsoluteFrequency;
+use crate::synthesis::instrument::Instrument;
+use crate::synthesis::synth::{pan_gains, Stereo};
+use std::f64::consts::TAU;
+
+/// Longest period a string holds, in samples: down to 5.9 Hz at 48 kHz
+/// and 23.4 Hz at 192 kHz. Lower pitches play too high.
+const MAX_PERIOD: usize = 8192;
+
+/// Enough strings for a six note strum plus the tails of the previous one.
+pub const MAX_STRINGS: usize = 8;
+
+/// A plucked string after Karplus and Strong: a burst of noise going round
+/// a delay line one period long, and losing some of its highs on every
+/// round.
+#[derive(Debug, Clone)]
+pub struct PluckedString {
+    /// How much faster the highs die away than the fundamental, from 0
+    /// (ringing, like steel) to 1 (dull, like nylon or gut).
+    pub damping: f32,
+    /// How much of the highs the pluck puts in, from 0 (a soft finger) to
+    /// 1 (a hard pick).
+    pub brightness: f32,
+    /// Where the string is plucked, as a fraction of its length from the
+    /// bridge up to 0.5 in the middle. Harmonics with a node there stay
+    /// silent; 0 leaves them all in.
+    pub pick_position: f32,
+    /// Time for the fundamental of a held note to fall by 60 dB.
+    pub decay: Seconds,
+    /// Time for a string to be muted after its note ends.
+    pub release: Seconds,
+    strings: Vec<StringState>,
+}
+
+impl Default for PluckedString {
+    fn default() -> Self {
+        PluckedString {
+            damping: 0.5,
+            brightness: 0.7,
+            pick_position: 0.13,
+            decay: 3.0,
+            release: 0.05,
+            strings: vec![StringState::default(); MAX_STRINGS],
+        }
+    }
+}
+
+impl PluckedString {
+    pub fn with_damping(self, damping: f32) -> Self {
+        PluckedString { damping, ..self }
+    }
+
+    pub fn with_brightness(self, brightness: f32) -> Self {
+        PluckedString { brightness, ..self }
+    }
+
+    pub fn with_pick_position(self, pick_position: f32) -> Self {
+        PluckedString {
+            pick_position,
+            ..self
+        }
+    }
+
+    pub fn with_decay(self, decay: Seconds) -> Self {
+        assert!(decay > 0.0, "a string needs time to decay");
+        PluckedString { decay, ..self }
+    }
+
+    pub fn with_release(self, release: Seconds) -> Self {
+        PluckedString { release, ..self }
+    }
+
+    /// Plays up to `count` pitches at once, release tails included.
+    pub fn with_strings(self, count: usize) -> Self {
+        assert!(count > 0, "an instrument needs at least one string");
+        PluckedString {
+            strings: vec![StringState::default(); count],
+            ..self
+        }
+    }
+
+    /// The string a new pitch should take over: an idle one if there is
+    /// any, otherwise the one closest to the end of its release, otherwise
+    /// the one plucked longest ago, which has decayed the most.
+    fn free_string(&mut self) -> &mut StringState {
+        self.strings
+            .iter_mut()
+            .min_by_key(|string| match string.pitch {
+                None => (0, 0),
+                Some(_) => match string.release_left {
+                    Some(left) => (1, left),
+                    None => (2, u64::MAX - string.age),
+                },
+            })
+            .expect("an instrument needs at least one string")
+    }
+}
+
+impl Instrument for PluckedString {
+    fn note_on(
+        &mut self,
+        pitch: Pitch,
+        frequency: AbsoluteFrequency,
+        velocity: f32,
+        pan: f32,
+        sample_rate: f32,
+    ) {
+        let pluck = Pluck {
+            damping: self.damping,
+            brightness: self.brightness,
+            pick_position: self.pick_position,
+            decay: self.decay,
+            frequency,
+            velocity,
+            sample_rate,
+        };
+        let string = self.free_string();
+        string.pluck(&pluck);
+        string.pitch = Some(pitch);
+        string.pan = pan;
+    }
+
+    fn note_off(&mut self, pitch: Pitch, sample_rate: f32) {
+        let release = self.release_samples(sample_rate);
+        for string in self.strings.iter_mut() {
+            if string.pitch == Some(pitch) && string.release_left.is_none() {
+                string.release_left = Some(release);
+                string.release_samples = release;
+            }
+        }
+    }
+
+    /// Sums the strings, scaled down whenever their velocities add up to
+    /// more than two plucks at full strength so that strums do not clip.
+    fn render(&mut self, output: &mut [Stereo], _sample_rate: f32) {
+        for frame in output {
+            let total_velocity: f32 = self
+                .strings
+                .iter()
+                .filter(|string| string.pitch.is_some())
+                .map(|string| string.velocity)
+                .sum();
+            let mut sum = Stereo::default();
+            for string in self.strings.iter_mut() {
+                if string.pitch.is_none() {
+                    continue;
+                }
+                let value = string.next();
+                let (left, right) = pan_gains(string.pan);
+                sum.left += value * left;
+                sum.right += value * right;
+            }
+            *frame = sum.scaled(1.0 / (total_velocity * PEAK).max(1.0));
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.strings.iter().all(|string| string.pitch.is_none())
+    }
+
+    fn release_samples(&self, sample_rate: f32) -> u64 {
+        (self.release * sample_rate as f64).ceil() as u64 + 1
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "plucked string (damping {}, brightness {}, pick position {})",
+            self.damping, self.brightness, self.pick_position
+        )
+    }
+}
+
+/// Peak level of a pluck at full velocity.
+const PEAK: f32 = 0.5;
+
+/// Everything one pluck of a string depends on.
+struct Pluck {
+    damping: f32,
+    brightness: f32,
+    pick_position: f32,
+    decay: Seconds,
+    frequency: AbsoluteFrequency,
+    velocity: f32,
+    sample_rate: f32,
+}
+
+/// One sounding string of a `PluckedString`.
+///
+/// Each round through the loop takes exactly one period of the
+/// fundamental: `period` samples in the delay line, plus the phase delay
+/// of the two-point lowpass that does the damping, plus a fraction of a
+/// sample in an allpass filter.
+#[derive(Debug, Clone)]
+struct StringState {
+    delay: Vec<f32>,
+    period: usize,
+    position: usize,
+    /// Weight of the previous sample in the lowpass, from 0.1 to 0.5.
+    stretch: f32,
+    previous: f32,
+    allpass: f32,
+    allpass_in: f32,
+    allpass_out: f32,
+    /// Gain per round, which sets how fast the string decays.
+    gain: f32,
+    noise: u32,
+    pitch: Option<Pitch>,
+    velocity: f32,
+    pan: f32,
+    /// Samples since the pluck.
+    age: u64,
+    /// Samples left until the string is muted, `None` while it is held.
+    release_left: Option<u64>,
+    release_samples: u64,
+}
+
+impl Default for StringState {
+    fn default() -> Self {
+        StringState {
+            delay: vec![0.0; MAX_PERIOD],
+            period: 1,
+            position: 0,
+            stretch: 0.0,
+            previous: 0.0,
+            allpass: 0.0,
+            allpass_in: 0.0,
+            allpass_out: 0.0,
+            gain: 0.0,
+            noise: 0x9E37_79B9,
+            pitch: None,
+            velocity: 0.0,
+            pan: 0.0,
+            age: 0,
+            release_left: None,
+            release_samples: 0,
+        }
+    }
+}
+
+impl StringState {
+    fn pluck(&mut self, pluck: &Pluck) {
+        let period = pluck.sample_rate as f64 / pluck.frequency as f64;
+        let omega = TAU / period;
+
+        // The lowpass (1 - s) + s z^-1 delays the fundamental by its phase
+        // over its frequency, which is exactly s only at DC and at s = 0.5.
+        // Some damping is always left: without it the highs ring on, each
+        // detuned by the allpass.
+        let stretch = 0.1 + 0.4 * pluck.damping.clamp(0.0, 1.0) as f64;
+        let lowpass_delay =
+            (stretch * omega.sin()).atan2(1.0 - stretch + stretch * omega.cos()) / omega;
+        let rest = period - lowpass_delay;
+        let whole = ((rest - 0.5).floor() as usize).clamp(1, MAX_PERIOD);
+        // Kept between 0.5 and 1.5 samples, where the allpass is stable
+        // and its phase delay flat enough for the harmonics.
+        let fraction = (rest - whole as f64).clamp(0.5, 1.5);
+        // The first order allpass whose phase delay at the fundamental is
+        // exactly `fraction`.
+        let allpass =
+            (omega * (1.0 - fraction) / 2.0).sin() / (omega * (1.0 + fraction) / 2.0).sin();
+
+        self.period = whole;
+        self.position = 0;
+        self.stretch = stretch as f32;
+        self.previous = 0.0;
+        self.allpass = allpass as f32;
+        self.allpass_in = 0.0;
+        self.allpass_out = 0.0;
+        self.gain = 10f64.powf(-3.0 / (pluck.decay * pluck.frequency as f64)) as f32;
+        self.velocity = pluck.velocity;
+        self.age = 0;
+        self.release_left = None;
+        self.excite(pluck);
+    }
+
+    /// Fills one period with lowpassed noise, shaped by where the string
+    /// is plucked, without DC and peaking at the velocity.
+    fn excite(&mut self, pluck: &Pluck) {
+        let burst = &mut self.delay[..self.period];
+        let smoothing = pluck.brightness.clamp(0.05, 1.0);
+        let mut low = 0.0;
+        for sample in burst.iter_mut() {
+            self.noise ^= self.noise << 13;
+            self.noise ^= self.noise >> 17;
+            self.noise ^= self.noise << 5;
+            let white = self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0;
+            low += (white - low) * smoothing;
+            *sample = low;
+        }
+
+        // The wave reflected at the near end of the string cancels the
+        // harmonics with a node at the pick. Going down keeps the samples
+        // still to be read unchanged.
+        let offset = (pluck.pick_position.clamp(0.0, 0.5) * burst.len() as f32).round() as usize;
+        if offset > 0 {
+            for index in (offset..burst.len()).rev() {
+                burst[index] -= burst[index - offset];
+            }
+        }
+
+        let mean = burst.iter().sum::<f32>() / burst.len() as f32;
+        let peak = burst
+            .iter()
+            .fold(0f32, |peak, sample| peak.max((sample - mean).abs()));
+        let scale = if peak > 0.0 {
+            PEAK * pluck.velocity / peak
+        } else {
+            0.0
+        };
+        for sample in burst.iter_mut() {
+            *sample = (*sample - mean) * scale;
+        }
+    }
+
+    fn next(&mut self) -> f32 {
+        let out = self.delay[self.position];
+        let low = (1.0 - self.stretch) * out + self.stretch * self.previous;
+        self.previous = out;
+        let shifted = self.allpass * low + self.allpass_in - self.allpass * self.allpass_out;
+        self.allpass_in = low;
+        self.allpass_out = shifted;
+        self.delay[self.position] = self.gain * shifted;
+        self.position = (self.position + 1) % self.period;
+        self.age += 1;
+
+        let level = match self.release_left {
+            None => 1.0,
+            Some(0) => {
+                self.pitch = None;
+                0.0
+            }
+            Some(left) => {
+                self.release_left = Some(left - 1);
+                left as f32 / self.release_samples as f32
+            }
+        };
+        out * level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pluck(string: &mut PluckedString, pitch: &str, frames: usize) -> Vec<f32> {
+        let pitch: Pitch = pitch.parse().unwrap();
+        string.note_on(pitch, pitch.frequency(), 1.0, 0.0, 48000.0);
+        let mut block = vec![Stereo::default(); frames];
+        string.render(&mut block, 48000.0);
+        block.iter().map(Stereo::mono).collect()
+    }
+
+    /// Frequency of the strongest period in `samples` by autocorrelation,
+    /// refined between samples by a parabola through the peak.
+    fn estimate_pitch(samples: &[f32], sample_rate: f32) -> f32 {
+        let correlation = |lag: usize| -> f64 {
+            samples
+                .iter()
+                .zip(&samples[lag..])
+                .map(|(a, b)| *a as f64 * *b as f64)
+                .sum()
+        };
+        let lags = (sample_rate / 2000.0) as usize..(sample_rate / 40.0) as usize;
+        let values: Vec<f64> = lags.clone().map(correlation).collect();
+        let best = values.iter().cloned().fold(f64::MIN, f64::max);
+        // The first lag nearly as strong as the best is the period; later
+        // ones are its multiples.
+        let index = (1..values.len() - 1)
+            .find(|&i| {
+                values[i] > 0.9 * best && values[i] >= values[i - 1] && values[i] >= values[i + 1]
+            })
+            .unwrap();
+        let (before, peak, after) = (values[index - 1], values[index], values[index + 1]);
+        let offset = 0.5 * (before - after) / (before - 2.0 * peak + after);
+        sample_rate / (lags.start as f64 + index as f64 + offset) as f32
+    }
+
+    fn cents(actual: f32, expected: f32) -> f32 {
+        1200.0 * (actual / expected).log2()
+    }
+
+    #[test]
+    fn plucks_sound_at_their_pitch() {
+        for pitch in ["E2", "A2", "G3", "A4", "C#5", "E6"] {
+            for damping in [0.0, 0.5, 1.0] {
+                let mut string = PluckedString::default().with_damping(damping);
+                let samples = pluck(&mut string, pitch, 7200);
+                let expected = pitch.parse::<Pitch>().unwrap().frequency();
+                let estimated = estimate_pitch(&samples[2400..], 48000.0);
+                assert!(
+                    cents(estimated, expected).abs() < 2.0,
+                    "{} with damping {} sounds at {} Hz instead of {} Hz",
+                    pitch,
+                    damping,
+                    estimated,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn strings_decay_and_are_muted_on_release() {
+        let mut string = PluckedString::default().with_decay(0.5);
+        let samples = pluck(&mut string, "A3", 48000);
+        let peak = |samples: &[f32]| samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak(&samples[..4800]) <= PEAK);
+        assert!(peak(&samples[..4800]) > 0.2);
+        // 60 dB in half a second.
+        assert!(peak(&samples[24000..28800]) < 0.002);
+        assert!(!string.is_idle());
+
+        string.note_off("A3".parse().unwrap(), 48000.0);
+        let release = string.release_samples(48000.0) as usize;
+        let mut block = vec![Stereo::default(); release + 1];
+        string.render(&mut block, 48000.0);
+        assert!(string.is_idle());
+        assert_eq!(block[release], Stereo::default());
+    }
+
+    #[test]
+    fn pick_position_and_brightness_shape_the_tone() {
+        // Harmonic energy above the fundamental, from how much the signal
+        // changes between samples.
+        let roughness = |string: PluckedString| {
+            let mut string = string;
+            let samples = pluck(&mut string, "A3", 4800);
+            let power: f32 = samples.iter().map(|s| s * s).sum();
+            let changes: f32 = samples.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum();
+            changes / power
+        };
+        let default = roughness(PluckedString::default());
+        assert!(roughness(PluckedString::default().with_brightness(0.1)) < default);
+        assert!(roughness(PluckedString::default().with_damping(1.0)) < default);
+        // Plucking in the middle leaves out every even harmonic.
+        assert!(roughness(PluckedString::default().with_pick_position(0.5)) < default);
+    }
+
+    #[test]
+    fn strums_do_not_clip() {
+        let mut guitar = PluckedString::default();
+        for (index, pitch) in ["E2", "B2", "E3", "G#3", "B3", "E4"].iter().enumerate() {
+            let pitch: Pitch = pitch.parse().unwrap();
+            guitar.note_on(
+                pitch,
+                pitch.frequency(),
+                1.0,
+                index as f32 / 5.0 - 0.5,
+                48000.0,
+            );
+        }
+        let mut block = vec![Stereo::default(); 48000];
+        guitar.render(&mut block, 48000.0);
+        assert!(block
+            .iter()
+            .all(|sample| sample.left.abs() <= 1.0 && sample.right.abs() <= 1.0));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::model::pitch::Pitch;
+use crate::model::AbsoluteFrequency;
+use crate::synthesis::envelope::{Envelope, EnvelopeState};
+use crate::synthesis::instrument::Instrument;
+use crate::synthesis::oscillator::{Oscillator, Waveform};
+
+/// The built-in instrument: an oscillator shaped by an envelope for every
+/// pitch, out of a fixed pool of voices.
+#[derive(Debug, Clone, Default)]
+pub struct Synth {
+    pub waveform: Waveform,
+    pub envelope: Envelope,
+    voices: Voices,
+}
+
+impl Synth {
+    pub fn with_waveform(self, waveform: Waveform) -> Self {
+        Synth { waveform, ..self }
+    }
+
+    pub fn with_envelope(self, envelope: Envelope) -> Self {
+        Synth { envelope, ..self }
+    }
+
+    /// Plays up to `count` pitches at once, release tails included.
+    pub fn with_voices(self, count: usize) -> Self {
+        Synth {
+            voices: Voices::new(count),
+            ..self
+        }
+    }
+
+    pub fn voices(&self) -> &Voices {
+        &self.voices
+    }
+}
+
+impl Instrument for Synth {
+    fn note_on(
+        &mut self,
+        pitch: Pitch,
+        frequency: AbsoluteFrequency,
+        velocity: f32,
+        pan: f32,
+        _sample_rate: f32,
+    ) {
+        self.voices.note_on(pitch, frequency, velocity, pan);
+    }
+
+    fn note_off(&mut self, pitch: Pitch, sample_rate: f32) {
+        self.voices.note_off(pitch, &self.envelope, sample_rate);
+    }
+
+    fn render(&mut self, output: &mut [Stereo], sample_rate: f32) {
+        for frame in output {
+            *frame = self.voices.next(self.waveform, &self.envelope, sample_rate);
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.voices.is_idle()
+    }
+
+    fn release_samples(&self, sample_rate: f32) -> u64 {
+        self.envelope.release_samples(sample_rate)
+    }
+
+    fn describe(&self) -> String {
+        format!("{:?}", self.waveform)
+    }
+}
+
+/// A single sounding pitch of a `Synth`.
+///
+/// A voice keeps running through its release after the note ended, while
+/// the next note already plays on another voice.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Voice {
+    oscillator: Oscillator,
+    envelope: EnvelopeState,
+    pitch: Option<Pitch>,
+    frequency: AbsoluteFrequency,
+    /// Gain of the note, from 0 to 1.
+    velocity: f32,
+    /// Position in the stereo field from -1 (left) to 1 (right).
+    pan: f32,
+}
+
+impl Voice {
+    pub fn start(&mut self, pitch: Pitch, frequency: AbsoluteFrequency, velocity: f32, pan: f32) {
+        if self.envelope.is_idle() {
+            self.oscillator.reset();
+        }
+        self.pitch = Some(pitch);
+        self.frequency = frequency;
+        self.velocity = velocity;
+        self.pan = pan;
+        self.envelope.note_on();
+    }
+
+    pub fn release(&mut self, envelope: &Envelope, sample_rate: f32) {
+        self.envelope.note_off(envelope, sample_rate);
+    }
+
+    /// The pitch this voice holds, `None` once it is released.
+    pub fn held(&self) -> Option<Pitch> {
+        if self.is_idle() || self.envelope.is_releasing() {
+            return None;
+        }
+        self.pitch
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.envelope.is_idle()
+    }
+
+    /// How much stealing this voice would be heard: idle voices go first,
+    /// then releasing ones, each from the quietest.
+    fn audibility(&self) -> (u8, f32) {
+        let stage = if self.is_idle() {
+            0
+        } else if self.envelope.is_releasing() {
+            1
+        } else {
+            2
+        };
+        (stage, self.loudness())
+    }
+
+    fn loudness(&self) -> f32 {
+        self.envelope.level() * self.velocity
+    }
+
+    pub fn next(&mut self, waveform: Waveform, envelope: &Envelope, sample_rate: f32) -> f32 {
+        if self.is_idle() {
+            return 0.0;
+        }
+        let level = self.envelope.next(envelope, sample_rate);
+        level * self.velocity * self.oscillator.next(waveform, self.frequency, sample_rate)
+    }
+}
+
+/// Enough voices for an eight note chord plus the release tails of the
+/// previous one.
+pub const MAX_VOICES: usize = 16;
+
+/// A fixed pool of voices that notes are allocated to as they start.
+#[derive(Debug, Clone)]
+pub struct Voices {
+    voices: Vec<Voice>,
+}
+
+impl Default for Voices {
+    fn default() -> Self {
+        Voices::new(MAX_VOICES)
+    }
+}
+
+impl Voices {
+    pub fn new(count: usize) -> Self {
+        assert!(count > 0, "a synth needs at least one voice");
+        Voices {
+            voices: vec![Voice::default(); count],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Voice> {
+        self.voices.iter()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.voices.iter().all(Voice::is_idle)
+    }
+
+    pub fn note_on(&mut self, pitch: Pitch, frequency: AbsoluteFrequency, velocity: f32, pan: f32) {
+        self.free_voice().start(pitch, frequency, velocity, pan);
+    }
+
+    /// Releases every voice holding `pitch`.
+    pub fn note_off(&mut self, pitch: Pitch, envelope: &Envelope, sample_rate: f32) {
+        for voice in self.voices.iter_mut() {
+            if voice.held() == Some(pitch) {
+                voice.release(envelope, sample_rate);
+            }
+        }
+    }
+
+    /// The voice a new pitch should take over: an idle one if there is any,
+    /// otherwise the quietest releasing one, otherwise the quietest one.
+    fn free_voice(&mut self) -> &mut Voice {
+        self.voices
+            .iter_mut()
+            .min_by(|a, b| {
+                a.audibility()
+                    .partial_cmp(&b.audibility())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .expect("a synth needs at least one voice")
+    }
+
+    /// Sums all voices, each placed at its pan position, and scaled down
+    /// whenever their levels add up to more than one so that chords do not
+    /// clip.
+    pub fn next(&mut self, waveform: Waveform, envelope: &Envelope, sample_rate: f32) -> Stereo {
+        let total_level: f32 = self.voices.iter().map(Voice::loudness).sum();
+        let mut sum = Stereo::default();
+        for voice in self.voices.iter_mut().filter(|voice| !voice.is_idle()) {
+            let value = voice.next(waveform, envelope, sample_rate);
+            let (left, right) = pan_gains(voice.pan);
+            sum.left += value * left;
+            sum.right += value * right;
+        }
+        sum.scaled(1.0 / total_level.max(1.0))
+    }
+}
+
+/// A left and right sample.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stereo {
+    pub left: f32,
+    pub right: f32,
+}
+
+impl Stereo {
+    pub fn scaled(self, gain: f32) -> Self {
+        Stereo {
+            left: self.left * gain,
+            right: self.right * gain,
+        }
+    }
+
+    /// Folds both sides into one, so that a centred sound keeps the level
+    /// it had before panning.
+    pub fn mono(&self) -> f32 {
+        (self.left + self.right) * std::f32::consts::FRAC_1_SQRT_2
+    }
+}
+
+/// Left and right gains for `pan` under a constant-power law, so a sound
+/// keeps its loudness as it moves across; the centre is 3 dB down on each
+/// side. Positions beyond the edges stay at the edge.
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::length::ToneLength;
+    use crate::model::Note;
+    use crate::synthesis::instrument::change_chord;
+    use crate::synthesis::tuning::Tuning;
+
+    fn chord(names: &[&str]) -> Note {
+        let pitches = names.iter().map(|name| name.parse::<Pitch>().unwrap());
+        Note::chord(pitches, ToneLength::Full)
+    }
+
+    fn play(synth: &mut Synth, from: Option<&Note>, to: &Note, width: f32) {
+        change_chord(
+            synth,
+            from,
+            Some(to),
+            &Tuning::default(),
+            0.0,
+            width,
+            48000.0,
+        );
+    }
+
+    fn held(synth: &Synth) -> usize {
+        synth
+            .voices()
+            .iter()
+            .filter(|voice| voice.held().is_some())
+            .count()
+    }
+
+    fn sounding(synth: &Synth) -> usize {
+        synth
+            .voices()
+            .iter()
+            .filter(|voice| !voice.is_idle())
+            .count()
+    }
+
+    fn next(synth: &mut Synth) -> Stereo {
+        let mut frame = [Stereo::default()];
+        synth.render(&mut frame, 48000.0);
+        frame[0]
+    }
+
+    #[test]
+    fn eight_note_chords_get_a_voice_each() {
+        let mut synth = Synth::default();
+        let first = chord(&["C3", "G3", "C4", "E4", "G4", "Bb4", "C5", "E5"]);
+        play(&mut synth, None, &first, 0.0);
+        assert_eq!(held(&synth), 8);
+
+        let next = chord(&["F3", "C4", "F4", "A4", "C5", "Eb5", "F5", "A5"]);
+        play(&mut synth, Some(&first), &next, 0.0);
+        assert_eq!(held(&synth), 8);
+        assert_eq!(sounding(&synth), 16);
+    }
+
+    #[test]
+    fn tied_pitches_keep_their_voice() {
+        let mut synth = Synth::default();
+        let first = chord(&["C4", "E4"]).tied();
+        play(&mut synth, None, &first, 0.0);
+        play(&mut synth, Some(&first), &chord(&["C4", "F4"]), 0.0);
+
+        assert_eq!(held(&synth), 2);
+        assert_eq!(sounding(&synth), 3);
+    }
+
+    #[test]
+    fn stealing_prefers_releasing_voices() {
+        let mut synth = Synth::default().with_voices(2);
+        let first = chord(&["C4"]);
+        let second = chord(&["D4"]).tied();
+        play(&mut synth, None, &first, 0.0);
+        play(&mut synth, Some(&first), &second, 0.0);
+        play(&mut synth, Some(&second), &chord(&["D4", "E4"]), 0.0);
+
+        assert_eq!(held(&synth), 2);
+    }
+
+    #[test]
+    fn chords_are_normalised() {
+        let mut synth = Synth::default().with_waveform(Waveform::Square);
+        let chord = chord(&["C3", "C4", "C5", "C6", "C7", "G4", "G5", "G6"]);
+        play(&mut synth, None, &chord, 1.0);
+        let mut block = vec![Stereo::default(); 48000];
+        synth.render(&mut block, 48000.0);
+        assert!(block
+            .iter()
+            .all(|sample| sample.left.abs() <= 1.0 && sample.right.abs() <= 1.0));
+    }
+
+    #[test]
+    fn velocity_scales_the_voice() {
+        let mut loud = Synth::default();
+        let mut soft = Synth::default();
+        let note = chord(&["A4"]);
+        play(&mut loud, None, &note, 0.0);
+        play(&mut soft, None, &note.with_velocity(0.25), 0.0);
+        for _ in 0..4800 {
+            let (loud, soft) = (next(&mut loud), next(&mut soft));
+            assert!((soft.left - loud.left * 0.25).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn panning_keeps_constant_power() {
+        for pan in [-1.0, -0.5, 0.0, 0.3, 1.0] {
+            let (left, right) = pan_gains(pan);
+            assert!((left * left + right * right - 1.0).abs() < 1e-6);
+        }
+        assert!(pan_gains(-1.0).1.abs() < 1e-6);
+        assert!(pan_gains(1.5).0.abs() < 1e-6);
+        let (left, right) = pan_gains(0.0);
+        assert!((left - right).abs() < 1e-6);
+        assert!((Stereo { left, right }.mono() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn width_spreads_chords_from_low_to_high() {
+        let c_major = chord(&["G4", "C4", "E4"]);
+        let mut wide = Synth::default();
+        play(&mut wide, None, &c_major, 1.0);
+        let pans: Vec<(String, f32)> = wide
+            .voices()
+            .iter()
+            .filter(|voice| !voice.is_idle())
+            .map(|voice| (voice.pitch.unwrap().to_string(), voice.pan))
+            .collect();
+        assert_eq!(
+            pans,
+            vec![
+                ("G4".to_string(), 1.0),
+                ("C4".to_string(), -1.0),
+                ("E4".to_string(), 0.0)
+            ]
+        );
+
+        let mut narrow = Synth::default();
+        play(&mut narrow, None, &c_major, 0.0);
+        for _ in 0..1000 {
+            let sample = next(&mut narrow);
+            assert!((sample.left - sample.right).abs() < 1e-6);
+            let sample = next(&mut wide);
+            assert!(sample.left.abs() < 1e-6 || (sample.left - sample.right).abs() > 1e-6);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use crate::model::length::NoteLength;
+use crate::model::tempo::Tempo;
+use crate::model::Melody;
+
+/// The notes of a melody laid out in samples, so that the audio callback
+/// finds the sounding note without allocating.
+///
+/// Lookups start from the note found last: playback moving forward takes a
+/// step or two, a jump anywhere else a binary search.
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    /// Sample at which each note ends.
+    ends: Vec<u64>,
+    /// Note each note strikes: the first of its tie chain, `None` for a
+    /// rest.
+    strikes: Vec<Option<usize>>,
+    /// Index of the note found last.
+    cursor: usize,
+}
+
+impl Timeline {
+    pub fn new(melody: &Melody, tempo: Tempo, sample_rate: f32) -> Self {
+        let mut ends = Vec::with_capacity(melody.melody.len());
+        let mut end = NoteLength::ZERO;
+        for note in &melody.melody {
+            end = end + note.length;
+            ends.push(tempo.sample_at(end, sample_rate));
+        }
+        let strikes = (0..melody.melody.len())
+            .map(|index| melody.strike(index))
+            .collect();
+        Timeline {
+            ends,
+            strikes,
+            cursor: 0,
+        }
+    }
+
+    /// The sample after the last note.
+    pub fn end(&self) -> u64 {
+        self.ends.last().copied().unwrap_or(0)
+    }
+
+    /// Index of the note to strike at `sample`, or `None` during a rest and
+    /// after the end.
+    pub fn note_at(&mut self, sample: u64) -> Option<usize> {
+        let index = self.index_at(sample)?;
+        self.strike(index)
+    }
+
+    /// Index of the note playing at `sample`, which may be tied on from the
+    /// one struck, or `None` during a rest and after the end.
+    pub fn index_at(&mut self, sample: u64) -> Option<usize> {
+        if !self.contains(self.cursor, sample) {
+            self.cursor = if self.contains(self.cursor + 1, sample) {
+                self.cursor + 1
+            } else {
+                self.ends.partition_point(|&end| end <= sample)
+            };
+        }
+        self.strikes.get(self.cursor)?.map(|_| self.cursor)
+    }
+
+    /// Index of the note that note `index` strikes.
+    pub fn strike(&self, index: usize) -> Option<usize> {
+        self.strikes[index]
+    }
+
+    /// Whether `sample` falls within note `index`, or after the end for the
+    /// index past the last note.
+    fn contains(&self, index: usize, sample: u64) -> bool {
+        let start = index
+            .checked_sub(1)
+            .map_or(Some(0), |before| self.ends.get(before).copied());
+        start.is_some_and(|start| start <= sample)
+            && self.ends.get(index).is_none_or(|&end| sample < end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::length::ToneLength;
+    use crate::model::Note;
+
+    fn melody() -> Melody {
+        Melody {
+            melody: vec![
+                Note::new(1.0, ToneLength::Quarter),
+                Note::new(1.0, ToneLength::Quarter).tied(),
+                Note::new(1.0, ToneLength::Quarter),
+                Note::rest(ToneLength::Quarter),
+                Note::new(2.0, ToneLength::Half),
+            ],
+        }
+    }
+
+    #[test]
+    fn lookups_walk_forward_and_seek_back() {
+        // A quarter note lasts 100 samples.
+        let tempo = Tempo::new(60.0);
+        let mut timeline = Timeline::new(&melody(), tempo, 100.0);
+        assert_eq!(timeline.end(), 600);
+
+        let expected = |sample| match sample {
+            0..=99 => Some(0),
+            100..=299 => Some(1),
+            300..=399 => None,
+            400..=599 => Some(4),
+            _ => None,
+        };
+        for sample in 0..700 {
+            assert_eq!(timeline.note_at(sample), expected(sample), "{}", sample);
+        }
+        for sample in [450, 0, 599, 250, 600, 99, 100, 1000, 300] {
+            assert_eq!(timeline.note_at(sample), expected(sample), "{}", sample);
+        }
+        assert_eq!(timeline.index_at(150), Some(1));
+        assert_eq!(timeline.index_at(250), Some(2));
+        assert_eq!(timeline.index_at(350), None);
+
+        let melody = melody();
+        for sample in [0, 150, 250, 350, 550] {
+            let beat = NoteLength::new(sample, 400);
+            assert_eq!(
+                Timeline::new(&melody, tempo, 100.0).note_at(sample),
+                melody.beat_to_note(beat)
+            );
+        }
+    }
+
+    #[test]
+    fn empty_melodies_have_no_notes() {
+        let mut timeline = Timeline::new(&Melody { melody: vec![] }, Tempo::default(), 48000.0);
+        assert_eq!(timeline.end(), 0);
+        assert_eq!(timeline.note_at(0), None);
+        assert_eq!(timeline.note_at(100), None);
+    }
+}
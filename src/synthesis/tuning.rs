@@ -0,0 +1,306 @@
+use crate::model::pitch::{Letter, Pitch};
+use crate::model::{AbsoluteFrequency, A_IN_HZ};
+use std::fmt;
+use std::sync::Arc;
+
+/// Where a tuning system places each spelled pitch.
+pub trait TuningSystem: fmt::Debug + Send + Sync {
+    /// Cents from A4 up to `pitch`, leaving out the pitch's own `cents`,
+    /// or `None` for a pitch the system leaves silent.
+    fn cents_above_a4(&self, pitch: &Pitch) -> Option<f64>;
+}
+
+/// A tuning system at a concert pitch, the frequency of A4.
+#[derive(Debug, Clone)]
+pub struct Tuning {
+    pub concert_pitch: AbsoluteFrequency,
+    pub system: Arc<dyn TuningSystem>,
+}
+
+impl Tuning {
+    pub fn new(system: impl TuningSystem + 'static) -> Self {
+        Tuning {
+            concert_pitch: A_IN_HZ,
+            system: Arc::new(system),
+        }
+    }
+
+    pub fn with_concert_pitch(self, concert_pitch: AbsoluteFrequency) -> Self {
+        Tuning {
+            concert_pitch,
+            ..self
+        }
+    }
+
+    pub fn frequency(&self, pitch: &Pitch) -> Option<AbsoluteFrequency> {
+        let cents = self.system.cents_above_a4(pitch)? + pitch.cents as f64;
+        Some((self.concert_pitch as f64 * (cents / 1200.0).exp2()) as f32)
+    }
+}
+
+/// Twelve-tone equal temperament at A4 = 440 Hz.
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning::new(EqualTemperament)
+    }
+}
+
+/// Twelve equal semitones to the octave.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EqualTemperament;
+
+impl TuningSystem for EqualTemperament {
+    fn cents_above_a4(&self, pitch: &Pitch) -> Option<f64> {
+        Some(100.0 * (pitch.midi() - A4.midi()) as f64)
+    }
+}
+
+pub(crate) const A4: Pitch = Pitch {
+    letter: Letter::A,
+    accidental: 0,
+    octave: 4,
+    cents: 0.0,
+};
+
+/// Every pitch reached from A by a chain of equal fifths and pure octaves,
+/// following its spelling: F# is four fifths up from D, Gb six down, and
+/// the two differ unless twelve fifths make seven octaves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegularTemperament {
+    pub fifth_cents: f64,
+}
+
+impl RegularTemperament {
+    /// Pure 3/2 fifths.
+    pub fn pythagorean() -> Self {
+        RegularTemperament {
+            fifth_cents: 1200.0 * 1.5f64.log2(),
+        }
+    }
+
+    /// Quarter-comma meantone, whose fifths are narrowed until four of them
+    /// make a pure 5/4 major third.
+    pub fn meantone() -> Self {
+        RegularTemperament {
+            fifth_cents: 1200.0 * 5f64.log2() / 4.0,
+        }
+    }
+
+    /// `divisions` equal steps to the octave, with the fifth the step
+    /// nearest to 3/2; 19 and 31 tell sharps from flats, 12 does not.
+    pub fn edo(divisions: u32) -> Self {
+        let step = 1200.0 / divisions as f64;
+        RegularTemperament {
+            fifth_cents: (1200.0 * 1.5f64.log2() / step).round() * step,
+        }
+    }
+}
+
+impl TuningSystem for RegularTemperament {
+    fn cents_above_a4(&self, pitch: &Pitch) -> Option<f64> {
+        let fifths = fifths_from_a(pitch);
+        // In twelve-tone terms each fifth is seven semitones, so what is
+        // left over is a whole number of octaves.
+        let semitones = pitch.midi() - A4.midi();
+        let octaves = (semitones - 7 * fifths).div_euclid(12);
+        Some(fifths as f64 * self.fifth_cents + octaves as f64 * 1200.0)
+    }
+}
+
+/// Place of `pitch` on the line of fifths, counted from A.
+fn fifths_from_a(pitch: &Pitch) -> i32 {
+    let letter = match pitch.letter {
+        Letter::F => -1,
+        Letter::C => 0,
+        Letter::G => 1,
+        Letter::D => 2,
+        Letter::A => 3,
+        Letter::E => 4,
+        Letter::B => 5,
+    };
+    letter + 7 * pitch.accidental as i32 - 3
+}
+
+/// A tuning for the twelve semitones above a tonic, repeating every
+/// octave, with A4 kept at the concert pitch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntervalTable {
+    /// Semitones of the tonic above C.
+    pub tonic: i32,
+    /// Cents above the tonic of each semitone, the tonic itself first.
+    pub cents: [f64; 12],
+}
+
+impl IntervalTable {
+    /// Five-limit just intonation on `tonic`.
+    pub fn just(tonic: &Pitch) -> Self {
+        const RATIOS: [(f64, f64); 12] = [
+            (1.0, 1.0),
+            (16.0, 15.0),
+            (9.0, 8.0),
+            (6.0, 5.0),
+            (5.0, 4.0),
+            (4.0, 3.0),
+            (45.0, 32.0),
+            (3.0, 2.0),
+            (8.0, 5.0),
+            (5.0, 3.0),
+            (9.0, 5.0),
+            (15.0, 8.0),
+        ];
+        IntervalTable {
+            tonic: tonic.midi().rem_euclid(12),
+            cents: RATIOS.map(|(numerator, denominator)| 1200.0 * (numerator / denominator).log2()),
+        }
+    }
+
+    /// Werckmeister III, the well temperament on C that narrows the fifths
+    /// C-G, G-D, D-A and B-F# by a quarter of the Pythagorean comma.
+    pub fn werckmeister_iii() -> Self {
+        IntervalTable {
+            tonic: 0,
+            cents: [
+                0.0, 90.225, 192.18, 294.135, 390.225, 498.045, 588.27, 696.09, 792.18, 888.27,
+                996.09, 1092.18,
+            ],
+        }
+    }
+
+    fn cents_above_c0(&self, pitch: &Pitch) -> f64 {
+        let semitones = pitch.midi() - 12 - self.tonic;
+        self.cents[semitones.rem_euclid(12) as usize] + 1200.0 * semitones.div_euclid(12) as f64
+    }
+}
+
+impl TuningSystem for IntervalTable {
+    fn cents_above_a4(&self, pitch: &Pitch) -> Option<f64> {
+        Some(self.cents_above_c0(pitch) - self.cents_above_c0(&A4))
+    }
+}
+
+/// Reads `equal`, `pythagorean`, `meantone`, `werckmeister3`, `just:<tonic>`
+/// such as `just:Eb`, or `<n>edo` such as `19edo`.
+pub fn parse_tuning_system(s: &str) -> anyhow::Result<Arc<dyn TuningSystem>> {
+    let lower = s.to_ascii_lowercase();
+    let system: Arc<dyn TuningSystem> = match lower.as_str() {
+        "equal" => Arc::new(EqualTemperament),
+        "pythagorean" => Arc::new(RegularTemperament::pythagorean()),
+        "meantone" => Arc::new(RegularTemperament::meantone()),
+        "werckmeister3" => Arc::new(IntervalTable::werckmeister_iii()),
+        _ => {
+            if lower.starts_with("just:") {
+                let tonic = &s["just:".len()..];
+                let tonic: Pitch = format!("{}4", tonic)
+                    .parse()
+                    .map_err(|_| anyhow::Error::msg(format!("'{}' is not a tonic", tonic)))?;
+                Arc::new(IntervalTable::just(&tonic))
+            } else if let Some(Ok(divisions)) = lower.strip_suffix("edo").map(str::parse::<u32>) {
+                anyhow::ensure!(divisions > 0, "An octave needs at least one step");
+                Arc::new(RegularTemperament::edo(divisions))
+            } else {
+                anyhow::bail!(
+                    "Unknown tuning '{}', expected equal, pythagorean, meantone, \
+                     werckmeister3, just:<tonic> or <n>edo",
+                    s
+                );
+            }
+        }
+    };
+    Ok(system)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cents(system: &dyn TuningSystem, from: &str, to: &str) -> f64 {
+        let cents = |name: &str| system.cents_above_a4(&name.parse().unwrap()).unwrap();
+        cents(to) - cents(from)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn concert_pitch_moves_every_note() {
+        let modern = Tuning::default();
+        assert_eq!(modern.frequency(&A4), Some(440.0));
+        assert!((modern.frequency(&"C4".parse().unwrap()).unwrap() - 261.6256).abs() < 1e-3);
+
+        let baroque = Tuning::default().with_concert_pitch(415.0);
+        assert_eq!(baroque.frequency(&A4), Some(415.0));
+        assert_eq!(baroque.frequency(&"A5".parse().unwrap()), Some(830.0));
+        assert!((baroque.frequency(&"A4+100c".parse().unwrap()).unwrap() - 439.677).abs() < 1e-3);
+    }
+
+    #[test]
+    fn just_intervals_are_pure() {
+        let just = IntervalTable::just(&"D4".parse().unwrap());
+        assert_close(cents(&just, "D4", "A4"), 1200.0 * 1.5f64.log2());
+        assert_close(cents(&just, "D4", "F#4"), 1200.0 * 1.25f64.log2());
+        assert_close(cents(&just, "D3", "D5"), 2400.0);
+        assert_eq!(just.cents_above_a4(&A4), Some(0.0));
+    }
+
+    #[test]
+    fn fifth_chains_follow_the_spelling() {
+        let pythagorean = RegularTemperament::pythagorean();
+        assert_close(cents(&pythagorean, "C4", "G4"), 701.955_000_865_387_4);
+        assert_close(
+            cents(&pythagorean, "C4", "E4"),
+            1200.0 * (81f64 / 64.0).log2(),
+        );
+        // The Pythagorean comma between G# and Ab.
+        assert_close(cents(&pythagorean, "Ab4", "G#4"), 23.460_010_384_649_47);
+        assert_close(cents(&pythagorean, "A3", "A4"), 1200.0);
+
+        let meantone = RegularTemperament::meantone();
+        assert_close(cents(&meantone, "C4", "E4"), 1200.0 * 1.25f64.log2());
+        assert!(cents(&meantone, "Ab4", "G#4") < 0.0);
+
+        let nineteen = RegularTemperament::edo(19);
+        assert_close(cents(&nineteen, "C4", "C#4"), 1200.0 / 19.0);
+        assert_close(cents(&nineteen, "C4", "Db4"), 2400.0 / 19.0);
+        assert_close(cents(&nineteen, "C4", "C5"), 1200.0);
+
+        let twelve = RegularTemperament::edo(12);
+        for pitch in ["C0", "F#3", "Bb5", "Cb4", "E#2"] {
+            let pitch = pitch.parse().unwrap();
+            assert_close(
+                twelve.cents_above_a4(&pitch).unwrap(),
+                EqualTemperament.cents_above_a4(&pitch).unwrap(),
+            );
+        }
+    }
+
+    #[test]
+    fn werckmeister_has_its_own_thirds() {
+        let werckmeister = IntervalTable::werckmeister_iii();
+        assert_close(cents(&werckmeister, "C4", "E4"), 390.225);
+        assert_close(cents(&werckmeister, "C#4", "F4"), 407.82);
+        assert_eq!(werckmeister.cents_above_a4(&A4), Some(0.0));
+    }
+
+    #[test]
+    fn parse_tuning_systems() {
+        for name in [
+            "equal",
+            "Pythagorean",
+            "meantone",
+            "werckmeister3",
+            "just:Eb",
+            "31edo",
+        ] {
+            assert!(parse_tuning_system(name).is_ok(), "{}", name);
+        }
+        for name in ["just:H", "0edo", "edo", "kirnberger"] {
+            assert!(parse_tuning_system(name).is_err(), "{}", name);
+        }
+    }
+}